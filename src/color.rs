@@ -0,0 +1,193 @@
+use std::fmt;
+
+use bmp::Pixel;
+
+/// Selects target pixels for `GrayImage::filter_by`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorFilter {
+    /// Each RGB channel is within `color ± threshold`
+    Rgb { color: Pixel, threshold: u8 },
+    /// Hue in degrees, saturation and value in `0..=1`. All ranges are inclusive.
+    /// Hue range wraps around 360 if `hue.0 > hue.1`
+    Hsv { hue: (f32, f32), saturation: (f32, f32), value: (f32, f32) },
+    /// Euclidean distance to `color` in CIE Lab space.
+    /// Lightness difference is multiplied by `lightness_weight`, so `< 1` tolerates brightness changes
+    Lab { color: Pixel, distance: f32, lightness_weight: f32 },
+}
+
+impl ColorFilter {
+    /// Returns pixel predicate. Expensive conversions are prepared once
+    pub fn matcher(&self) -> Box<dyn Fn(u8, u8, u8) -> bool> {
+        match *self {
+            ColorFilter::Rgb { color, threshold } => {
+                let t = threshold as i16;
+                Box::new(move |r, g, b| {
+                    (r as i16 - color.r as i16).abs() <= t &&
+                        (g as i16 - color.g as i16).abs() <= t &&
+                        (b as i16 - color.b as i16).abs() <= t
+                })
+            }
+            ColorFilter::Hsv { hue, saturation, value } => {
+                Box::new(move |r, g, b| {
+                    let (h, s, v) = rgb_to_hsv(r, g, b);
+                    let hue_ok = if hue.0 <= hue.1 {
+                        h >= hue.0 && h <= hue.1
+                    } else {
+                        h >= hue.0 || h <= hue.1
+                    };
+                    hue_ok && s >= saturation.0 && s <= saturation.1 && v >= value.0 && v <= value.1
+                })
+            }
+            ColorFilter::Lab { color, distance, lightness_weight } => {
+                let linear: Vec<f32> = (0..=255u8).map(srgb_to_linear).collect();
+                let target = linear_to_lab(linear[color.r as usize], linear[color.g as usize], linear[color.b as usize]);
+                let max = distance * distance;
+                Box::new(move |r, g, b| {
+                    let (l, a, b) = linear_to_lab(linear[r as usize], linear[g as usize], linear[b as usize]);
+                    let dl = (l - target.0) * lightness_weight;
+                    let (da, db) = (a - target.1, b - target.2);
+                    dl * dl + da * da + db * db <= max
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for ColorFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorFilter::Rgb { color, threshold } =>
+                write!(f, "rgb #{:02X}{:02X}{:02X} {}", color.r, color.g, color.b, threshold),
+            ColorFilter::Hsv { hue, saturation, value } =>
+                write!(f, "hsv {}-{} {}-{} {}-{}", hue.0, hue.1, saturation.0, saturation.1, value.0, value.1),
+            ColorFilter::Lab { color, distance, lightness_weight } =>
+                write!(f, "lab #{:02X}{:02X}{:02X} {} {}", color.r, color.g, color.b, distance, lightness_weight),
+        }
+    }
+}
+
+/// Returns (hue in degrees `0..360`, saturation `0..=1`, value `0..=1`)
+pub fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    return (hue, saturation, max);
+}
+
+/// Returns CIE Lab (D65) color
+#[cfg(test)]
+pub fn rgb_to_lab(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    linear_to_lab(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b))
+}
+
+fn srgb_to_linear(v: u8) -> f32 {
+    let v = v as f32 / 255.0;
+    return if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    };
+}
+
+fn linear_to_lab(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    // sRGB -> XYZ, normalized by D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    return (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bmp::{Pixel, px};
+
+    use crate::color::{ColorFilter, rgb_to_hsv, rgb_to_lab};
+    use crate::recognize::StageFilters;
+
+    fn assert_near(expected: (f32, f32, f32), actual: (f32, f32, f32), eps: f32) {
+        assert!((expected.0 - actual.0).abs() < eps, "{:?} != {:?}", expected, actual);
+        assert!((expected.1 - actual.1).abs() < eps, "{:?} != {:?}", expected, actual);
+        assert!((expected.2 - actual.2).abs() < eps, "{:?} != {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_rgb_to_hsv() {
+        assert_near((0.0, 1.0, 1.0), rgb_to_hsv(255, 0, 0), 0.01);
+        assert_near((120.0, 1.0, 1.0), rgb_to_hsv(0, 255, 0), 0.01);
+        assert_near((240.0, 1.0, 1.0), rgb_to_hsv(0, 0, 255), 0.01);
+        assert_near((0.0, 0.0, 0.5), rgb_to_hsv(128, 128, 128), 0.01);
+        assert_near((300.0, 1.0, 1.0), rgb_to_hsv(255, 0, 255), 0.01);
+        assert_near((71.6, 0.633, 0.929), rgb_to_hsv(0xD0, 0xED, 0x57), 0.1);
+    }
+
+    #[test]
+    fn test_rgb_to_lab() {
+        assert_near((100.0, 0.0, 0.0), rgb_to_lab(255, 255, 255), 0.1);
+        assert_near((0.0, 0.0, 0.0), rgb_to_lab(0, 0, 0), 0.1);
+        assert_near((53.24, 80.09, 67.20), rgb_to_lab(255, 0, 0), 0.2);
+    }
+
+    fn shift_brightness(p: Pixel, k: f32) -> Pixel {
+        let c = |v: u8| (v as f32 * k).round().min(255.0) as u8;
+        px!(c(p.r), c(p.g), c(p.b))
+    }
+
+    #[test]
+    fn test_brightness_shifted() {
+        let base = px!(0xD0, 0xED, 0x57);
+        let darker = shift_brightness(base, 0.75);
+        let brighter = shift_brightness(base, 1.07);
+        let other_hue = px!(0x57, 0xD0, 0xED);
+
+        // RGB box of the matrix preset misses dimmed matrix, brightness tolerant presets don't
+        let rgb = StageFilters::rgb().matrix.matcher();
+        assert!(rgb(base.r, base.g, base.b));
+        assert!(!rgb(darker.r, darker.g, darker.b));
+
+        let hsv = StageFilters::hsv().matrix.matcher();
+        for p in [base, darker, brighter] {
+            assert!(hsv(p.r, p.g, p.b), "{:?}", p);
+        }
+        assert!(!hsv(other_hue.r, other_hue.g, other_hue.b));
+        assert!(!hsv(0xF0, 0xF0, 0xF0));
+
+        let lab = StageFilters::lab().matrix.matcher();
+        for p in [base, darker, brighter] {
+            assert!(lab(p.r, p.g, p.b), "{:?}", p);
+        }
+        assert!(!lab(other_hue.r, other_hue.g, other_hue.b));
+        assert!(!lab(0xF0, 0xF0, 0xF0));
+    }
+
+    #[test]
+    fn test_hue_wraps_around() {
+        let hsv = ColorFilter::Hsv { hue: (340.0, 20.0), saturation: (0.5, 1.0), value: (0.5, 1.0) }.matcher();
+        assert!(hsv(255, 0, 0));
+        assert!(hsv(255, 0, 40));
+        assert!(hsv(255, 40, 0));
+        assert!(!hsv(0, 255, 0));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("rgb #D0ED57 50", ColorFilter::Rgb { color: px!(0xD0, 0xED, 0x57), threshold: 50 }.to_string());
+        assert_eq!("hsv 60-85 0.4-1 0.5-1", ColorFilter::Hsv { hue: (60.0, 85.0), saturation: (0.4, 1.0), value: (0.5, 1.0) }.to_string());
+        assert_eq!("lab #FFFFFF 20 0.5", ColorFilter::Lab { color: px!(255, 255, 255), distance: 20.0, lightness_weight: 0.5 }.to_string());
+    }
+}
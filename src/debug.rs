@@ -0,0 +1,453 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use bmp::{Pixel, px};
+
+use crate::color::ColorFilter;
+use crate::img::{GrayImage, into_image, RgbImage};
+use crate::ocr::{ActiveLine, CellState, MatrixState};
+
+const AREA_COLOR: Pixel = px!(0xFF, 0x00, 0xFF);
+const BAND_COLOR: Pixel = px!(0x00, 0x90, 0xFF);
+const TEXT_COLOR: Pixel = px!(0xFF, 0xFF, 0x00);
+const ERROR_COLOR: Pixel = px!(0xFF, 0x30, 0x30);
+const PATH_COLOR: Pixel = px!(0x30, 0xFF, 0x30);
+const ACTIVE_COLOR: Pixel = px!(0xFF, 0xFF, 0xFF);
+
+// each glyph is 3x5 pixels, scaled by `FONT_SCALE`
+const FONT_SCALE: u32 = 2;
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// Everything the bot has seen during single `execute`.
+/// It is drawn over the source screenshot to troubleshoot recognition
+#[derive(Default)]
+pub struct Overlay {
+    areas: Vec<(u32, u32, u32, u32)>,
+    // absolute matrix bands: (matrix area, columns, rows)
+    bands: Option<((u32, u32, u32, u32), Vec<(u32, u32)>, Vec<(u32, u32)>)>,
+    // absolute active line rectangle
+    active: Option<(u32, u32, u32, u32)>,
+    // absolute position of label, recognized cell, whether it is highlighted and uncertain
+    cells: Vec<(u32, u32, CellState, bool, bool)>,
+    path: Vec<(u32, u32)>,
+}
+
+impl Overlay {
+    /// Detected matrix/conditions/buffer rectangle
+    pub fn add_area(&mut self, area: &(u32, u32, u32, u32)) {
+        self.areas.push(*area);
+    }
+
+    /// Bands, cells and active line of `matrix`, which is recognized in `matrix_area`
+    pub fn add_matrix(&mut self, matrix_area: &(u32, u32, u32, u32), matrix: &MatrixState) {
+        let (left, top, _, _) = *matrix_area;
+        let columns: Vec<(u32, u32)> = matrix.columns.iter().map(|c| (left + c.start, left + c.end)).collect();
+        let rows: Vec<(u32, u32)> = matrix.rows.iter().map(|r| (top + r.start, top + r.end)).collect();
+
+        for (y, (row, cells)) in rows.iter().zip(matrix.cells.iter()).enumerate() {
+            for (x, (column, cell)) in columns.iter().zip(cells).enumerate() {
+                self.cells.push((column.0, row.1 + 2, cell.state, cell.highlighted, matrix.uncertain.contains(&(x, y))));
+            }
+        }
+
+        let (first_column, last_column) = (columns.first().unwrap(), columns.last().unwrap());
+        let (first_row, last_row) = (rows.first().unwrap(), rows.last().unwrap());
+        self.active = match matrix.active_line {
+            Some(ActiveLine::Row(y)) => Some((first_column.0, rows[y].0, last_column.1, rows[y].1)),
+            Some(ActiveLine::Column(x)) => Some((columns[x].0, first_row.0, columns[x].1, last_row.1)),
+            None => None,
+        };
+        self.bands = Some((*matrix_area, columns, rows));
+    }
+
+    /// Screen coordinates of solution steps
+    pub fn set_path(&mut self, path: Vec<(u32, u32)>) {
+        self.path = path;
+    }
+
+    /// Draws everything over `img` copy
+    pub fn render(&self, img: &RgbImage) -> RgbImage {
+        let mut dest = img.clone();
+
+        if let Some(((left, top, right, bottom), columns, rows)) = &self.bands {
+            for &(start, end) in columns.iter() {
+                draw_line(&mut dest, (start, *top), (start, *bottom), &BAND_COLOR);
+                draw_line(&mut dest, (end, *top), (end, *bottom), &BAND_COLOR);
+            }
+            for &(start, end) in rows.iter() {
+                draw_line(&mut dest, (*left, start), (*right, start), &BAND_COLOR);
+                draw_line(&mut dest, (*left, end), (*right, end), &BAND_COLOR);
+            }
+        }
+
+        for area in self.areas.iter() {
+            draw_rect(&mut dest, area, &AREA_COLOR);
+        }
+
+        if let Some(active) = &self.active {
+            draw_rect(&mut dest, active, &ACTIVE_COLOR);
+        }
+
+        // highlighted cells are marked with `*` like printed matrix
+        for &(x, y, state, highlighted, uncertain) in self.cells.iter() {
+            let mark = if highlighted { "*" } else { "" };
+            match state {
+                CellState::Code(cell) => {
+                    let color = if uncertain { &ERROR_COLOR } else { &TEXT_COLOR };
+                    draw_text(&mut dest, x, y, &format!("{:02X}{}", cell.code, mark), color);
+                    draw_text(&mut dest, x, y + (GLYPH_HEIGHT + 1) * FONT_SCALE, &format!("{:.3}", cell.error), color);
+                }
+                CellState::Used => draw_text(&mut dest, x, y, &format!("[]{}", mark), &TEXT_COLOR),
+            }
+        }
+
+        for (i, &point) in self.path.iter().enumerate() {
+            if i > 0 {
+                draw_arrow(&mut dest, self.path[i - 1], point, &PATH_COLOR);
+            }
+            let (x, y) = point;
+            draw_text(&mut dest, x + 4, y.saturating_sub((GLYPH_HEIGHT + 1) * FONT_SCALE), &(i + 1).to_string(), &PATH_COLOR);
+        }
+        return dest;
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, img: &RgbImage, path: P) -> Result<(), String> {
+        self.render(img).save(path).map_err(|e| format!("Overlay was not saved: {}", e))
+    }
+}
+
+static MASK_DUMP: Mutex<Option<MaskDump>> = Mutex::new(None);
+
+/// Saves every filtered mask alongside `source` screenshot with a manifest of crop rectangles and color filters
+pub struct MaskDump {
+    dir: PathBuf,
+    stem: String,
+    source: String,
+    entries: Vec<String>,
+}
+
+impl MaskDump {
+    pub fn new<P: AsRef<Path>>(source: P) -> Self {
+        let source = source.as_ref();
+        return Self {
+            dir: source.parent().map(Path::to_path_buf).unwrap_or_default(),
+            stem: source.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "capture".to_owned()),
+            source: source.file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+            entries: Vec::new(),
+        };
+    }
+
+    /// Saves `mask` as `<source>.<index>.<name>.bmp`. `area` is crop rectangle of source screenshot
+    pub fn add(&mut self, name: &str, mask: &GrayImage, area: &(u32, u32, u32, u32), filter: &ColorFilter) -> Result<(), String> {
+        let file = format!("{}.{}.{}.bmp", self.stem, self.entries.len(), name);
+        into_image(mask).save(self.dir.join(&file)).map_err(|e| format!("Mask {} was not saved: {}", file, e))?;
+
+        let (left, top, right, bottom) = *area;
+        self.entries.push(format!("{} {} {} {} {} {} {}", name, file, left, top, right, bottom, filter));
+
+        let manifest = self.dir.join(format!("{}.masks.txt", self.stem));
+        std::fs::write(&manifest, self.manifest()).map_err(|e| format!("Manifest was not saved: {}", e))
+    }
+
+    fn manifest(&self) -> String {
+        let mut result = format!("source {}\n# name file left top right bottom filter\n", self.source);
+        for entry in self.entries.iter() {
+            result.push_str(entry);
+            result.push('\n');
+        }
+        return result;
+    }
+}
+
+/// Enables mask dump for all further recognition. Masks are saved alongside `source` screenshot
+pub fn start_mask_dump<P: AsRef<Path>>(source: P) {
+    *MASK_DUMP.lock().unwrap() = Some(MaskDump::new(source));
+}
+
+pub fn stop_mask_dump() {
+    *MASK_DUMP.lock().unwrap() = None;
+}
+
+/// Does nothing if mask dump is not started
+pub fn dump_mask(name: &str, mask: &GrayImage, area: &(u32, u32, u32, u32), filter: &ColorFilter) {
+    if let Some(dump) = MASK_DUMP.lock().unwrap().as_mut() {
+        if let Err(e) = dump.add(name, mask, area, filter) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[inline]
+fn set_pixel(img: &mut RgbImage, x: i64, y: i64, color: &Pixel) {
+    if x >= 0 && y >= 0 && x < img.width() as i64 && y < img.height() as i64 {
+        img.set_pixel(x as u32, y as u32, *color);
+    }
+}
+
+fn draw_rect(img: &mut RgbImage, rect: &(u32, u32, u32, u32), color: &Pixel) {
+    let (left, top, right, bottom) = *rect;
+    draw_line(img, (left, top), (right, top), color);
+    draw_line(img, (left, bottom), (right, bottom), color);
+    draw_line(img, (left, top), (left, bottom), color);
+    draw_line(img, (right, top), (right, bottom), color);
+}
+
+/// Bresenham's line. Pixels outside of image are skipped
+fn draw_line(img: &mut RgbImage, from: (u32, u32), to: (u32, u32), color: &Pixel) {
+    draw_line_i64(img, (from.0 as i64, from.1 as i64), (to.0 as i64, to.1 as i64), color);
+}
+
+fn draw_line_i64(img: &mut RgbImage, from: (i64, i64), to: (i64, i64), color: &Pixel) {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        set_pixel(img, x, y, color);
+        if x == to.0 && y == to.1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+fn draw_arrow(img: &mut RgbImage, from: (u32, u32), to: (u32, u32), color: &Pixel) {
+    draw_line(img, from, to, color);
+
+    let (fx, fy) = (from.0 as f64, from.1 as f64);
+    let (tx, ty) = (to.0 as f64, to.1 as f64);
+    let len = ((tx - fx).powi(2) + (ty - fy).powi(2)).sqrt();
+    if len < 1.0 {
+        return;
+    }
+
+    // arrowhead: 2 short lines at ±30° from the reverse direction
+    let (ux, uy) = ((fx - tx) / len, (fy - ty) / len);
+    let head = f64::min(12.0, len / 2.0);
+    let (sin, cos) = (30f64.to_radians().sin(), 30f64.to_radians().cos());
+    for sign in [-1.0, 1.0] {
+        let hx = ux * cos - sign * uy * sin;
+        let hy = sign * ux * sin + uy * cos;
+        let end = ((tx + hx * head).round() as i64, (ty + hy * head).round() as i64);
+        draw_line_i64(img, (to.0 as i64, to.1 as i64), end, color);
+    }
+}
+
+/// Draws `text` with built-in 3x5 font. Unknown characters are drawn as spaces
+fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, color: &Pixel) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = match glyph(c) {
+            Some(g) => g,
+            None => continue,
+        };
+        let glyph_x = x + i as u32 * (GLYPH_WIDTH + 1) * FONT_SCALE;
+        for (dy, line) in glyph.iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                if line & (1 << (GLYPH_WIDTH - 1 - dx)) == 0 {
+                    continue;
+                }
+                for sy in 0..FONT_SCALE {
+                    for sx in 0..FONT_SCALE {
+                        set_pixel(
+                            img,
+                            (glyph_x + dx * FONT_SCALE + sx) as i64,
+                            (y + dy as u32 * FONT_SCALE + sy) as i64,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> Option<[u8; 5]> {
+    let g = match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b111, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b111, 0b100, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        _ => return None,
+    };
+    return Some(g);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bmp::{Pixel, px};
+
+    use crate::color::ColorFilter;
+    use crate::debug::{ACTIVE_COLOR, draw_arrow, draw_line, draw_rect, draw_text, ERROR_COLOR, MaskDump, Overlay, TEXT_COLOR};
+    use crate::img::{GrayImage, RgbImage};
+    use crate::ocr::{ActiveLine, CellMatch, CellState, Location, MatrixCell, MatrixState};
+
+    const BLACK: Pixel = px!(0, 0, 0);
+    const WHITE: Pixel = px!(255, 255, 255);
+
+    #[test]
+    fn test_draw_line() {
+        let mut img = RgbImage::new(5, 5);
+        draw_line(&mut img, (0, 0), (4, 4), &WHITE);
+        for i in 0..5 {
+            assert_eq!(WHITE, img.pixel(i, i));
+        }
+        assert_eq!(BLACK, img.pixel(1, 0));
+        assert_eq!(BLACK, img.pixel(0, 1));
+
+        // out of image pixels are skipped
+        draw_line(&mut img, (0, 2), (100, 2), &WHITE);
+        assert_eq!(WHITE, img.pixel(4, 2));
+    }
+
+    #[test]
+    fn test_draw_rect() {
+        let mut img = RgbImage::new(5, 5);
+        draw_rect(&mut img, &(1, 1, 3, 3), &WHITE);
+        assert_eq!(WHITE, img.pixel(1, 1));
+        assert_eq!(WHITE, img.pixel(3, 1));
+        assert_eq!(WHITE, img.pixel(1, 3));
+        assert_eq!(WHITE, img.pixel(3, 3));
+        assert_eq!(BLACK, img.pixel(2, 2));
+        assert_eq!(BLACK, img.pixel(0, 0));
+        assert_eq!(BLACK, img.pixel(4, 4));
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut img = RgbImage::new(20, 10);
+        draw_text(&mut img, 0, 0, "1", &WHITE);
+        // '1' middle column is filled, scaled by 2
+        for y in 0..10 {
+            assert_eq!(WHITE, img.pixel(2, y));
+            assert_eq!(WHITE, img.pixel(3, y));
+        }
+        assert_eq!(BLACK, img.pixel(5, 4));
+
+        // unknown characters are not drawn
+        let mut img = RgbImage::new(20, 10);
+        draw_text(&mut img, 0, 0, "_", &WHITE);
+        assert!(is_black(&img));
+    }
+
+    #[test]
+    fn test_draw_arrow() {
+        let mut img = RgbImage::new(40, 40);
+        draw_arrow(&mut img, (5, 20), (35, 20), &WHITE);
+        assert_eq!(WHITE, img.pixel(5, 20));
+        assert_eq!(WHITE, img.pixel(35, 20));
+        // arrowhead goes back from the end
+        assert!((20..35).any(|x| img.pixel(x, 14) == WHITE));
+        assert!((20..35).any(|x| img.pixel(x, 26) == WHITE));
+        assert!((0..20).all(|x| img.pixel(x, 14) == BLACK));
+    }
+
+    #[test]
+    fn test_render_keeps_source() {
+        let img = RgbImage::new(10, 10);
+        let mut overlay = Overlay::default();
+        overlay.add_area(&(1, 1, 8, 8));
+        overlay.set_path(vec![(2, 5), (7, 5)]);
+
+        let rendered = overlay.render(&img);
+        assert_ne!(BLACK, rendered.pixel(1, 1));
+        assert_ne!(BLACK, rendered.pixel(7, 5));
+        assert!(is_black(&img));
+    }
+
+    #[test]
+    fn test_render_matrix_state() {
+        // 2x2 matrix: the first row is active, its second cell is used
+        let code = |code: u8| CellState::Code(CellMatch { code, error: 0.01, second_error: 0.5 });
+        let matrix = MatrixState {
+            left: 0,
+            top: 0,
+            right: 40,
+            bottom: 40,
+            cells: vec![
+                vec![MatrixCell { state: code(0x1C), highlighted: true }, MatrixCell { state: CellState::Used, highlighted: true }],
+                vec![MatrixCell { state: code(0xE9), highlighted: false }, MatrixCell { state: code(0x55), highlighted: false }],
+            ],
+            active_line: Some(ActiveLine::Row(0)),
+            uncertain: vec![(0, 1)],
+            columns: vec![Location { start: 0, end: 20 }, Location { start: 40, end: 60 }],
+            rows: vec![Location { start: 0, end: 20 }, Location { start: 40, end: 60 }],
+        };
+        let mut overlay = Overlay::default();
+        overlay.add_matrix(&(10, 10, 90, 90), &matrix);
+
+        let rendered = overlay.render(&RgbImage::new(120, 120));
+        // active line border
+        assert_eq!(ACTIVE_COLOR, rendered.pixel(30, 10));
+        assert_eq!(ACTIVE_COLOR, rendered.pixel(30, 30));
+        // used cell label `[]*` starts with `[` top line
+        assert_eq!(TEXT_COLOR, rendered.pixel(50, 32));
+        assert_eq!(TEXT_COLOR, rendered.pixel(52, 32));
+        // uncertain code is drawn with error color
+        assert_eq!(ERROR_COLOR, rendered.pixel(10, 72));
+        assert_eq!(TEXT_COLOR, rendered.pixel(50, 72));
+    }
+
+    #[test]
+    fn test_mask_dump() {
+        let dir = std::env::temp_dir().join("cyberbot2077_test_mask_dump");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut img = RgbImage::new(4, 4);
+        img.set_pixel(1, 1, WHITE);
+        let mask = GrayImage::filter(&img, &WHITE, 10, 0, 0, 3, 2);
+
+        let mut dump = MaskDump::new(dir.join("screen.bmp"));
+        dump.add("matrix", &mask, &(0, 0, 3, 2), &ColorFilter::Rgb { color: px!(0xD0, 0xED, 0x57), threshold: 50 }).unwrap();
+        let hsv = ColorFilter::Hsv { hue: (60.0, 85.0), saturation: (0.4, 1.0), value: (0.5, 1.0) };
+        dump.add("buffer", &mask, &(0, 0, 3, 2), &hsv).unwrap();
+
+        let manifest = std::fs::read_to_string(dir.join("screen.masks.txt")).unwrap();
+        assert_eq!(
+            "source screen.bmp\n\
+             # name file left top right bottom filter\n\
+             matrix screen.0.matrix.bmp 0 0 3 2 rgb #D0ED57 50\n\
+             buffer screen.1.buffer.bmp 0 0 3 2 hsv 60-85 0.4-1 0.5-1\n",
+            manifest,
+        );
+
+        let saved = bmp::open(dir.join("screen.0.matrix.bmp")).unwrap();
+        assert_eq!(3, saved.get_width());
+        assert_eq!(2, saved.get_height());
+        assert_eq!(WHITE, saved.get_pixel(1, 1));
+        assert_eq!(BLACK, saved.get_pixel(0, 0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn is_black(img: &RgbImage) -> bool {
+        (0..img.height()).all(|y| (0..img.width()).all(|x| img.pixel(x, y) == BLACK))
+    }
+}
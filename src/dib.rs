@@ -0,0 +1,297 @@
+use bmp::{Pixel, px};
+
+use crate::img::RgbImage;
+
+const FILE_HEADER_SIZE: usize = 14;
+
+const CORE_HEADER_SIZE: usize = 12;
+const INFO_HEADER_SIZE: usize = 40;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// Decodes 24/32 bits per pixel BMP file (with `BM` file header) or raw DIB (clipboard `CF_DIB`/`CF_DIBV5`).
+/// Supports bottom-up and top-down images, `BITMAPINFOHEADER`..`BITMAPV5HEADER` and color masks
+pub fn decode(bytes: &[u8]) -> Result<RgbImage, String> {
+    let (dib, pixels_offset) = if bytes.starts_with(b"BM") {
+        let offset = read_u32(bytes, 10)? as usize;
+        if offset < FILE_HEADER_SIZE {
+            return Err(format!("Bad pixel data offset {}", offset));
+        }
+        (&bytes[FILE_HEADER_SIZE..], Some(offset - FILE_HEADER_SIZE))
+    } else {
+        (bytes, None)
+    };
+
+    let header_size = read_u32(dib, 0)? as usize;
+    let (width, height, bpp, compression, colors_used) = if header_size == CORE_HEADER_SIZE {
+        (read_u16(dib, 4)? as i64, read_u16(dib, 6)? as i64, read_u16(dib, 10)?, BI_RGB, 0)
+    } else if header_size >= INFO_HEADER_SIZE {
+        (read_i32(dib, 4)? as i64, read_i32(dib, 8)? as i64, read_u16(dib, 14)?, read_u32(dib, 16)?, read_u32(dib, 32)? as usize)
+    } else {
+        return Err(format!("Unsupported DIB header size {}", header_size));
+    };
+
+    if bpp != 24 && bpp != 32 {
+        return Err(format!("Unsupported bits per pixel {}", bpp));
+    }
+    if width <= 0 || height == 0 {
+        return Err(format!("Bad image size {}x{}", width, height));
+    }
+
+    // color masks are stored inside V2+ headers or right after `BITMAPINFOHEADER`
+    let (masks, masks_size) = match compression {
+        BI_RGB if bpp == 24 => ([0xFF0000, 0x00FF00, 0x0000FF], 0),
+        BI_RGB => ([0x00FF0000, 0x0000FF00, 0x000000FF], 0),
+        BI_BITFIELDS | BI_ALPHABITFIELDS if bpp == 32 => {
+            let masks_start = if header_size > INFO_HEADER_SIZE { INFO_HEADER_SIZE } else { header_size };
+            let masks = [read_u32(dib, masks_start)?, read_u32(dib, masks_start + 4)?, read_u32(dib, masks_start + 8)?];
+            let masks_size = match (header_size, compression) {
+                (INFO_HEADER_SIZE, BI_BITFIELDS) => 12,
+                (INFO_HEADER_SIZE, _) => 16,
+                _ => 0,
+            };
+            (masks, masks_size)
+        }
+        _ => return Err(format!("Unsupported compression {} for {} bits per pixel", compression, bpp)),
+    };
+
+    let pixels_offset = pixels_offset.unwrap_or(header_size + masks_size + colors_used * 4);
+    let top_down = height < 0;
+    let (width, height) = (width as u32, height.unsigned_abs() as u32);
+    let bytes_per_pixel = (bpp / 8) as usize;
+    let too_large = || format!("{}x{} image is too large", width, height);
+    let stride = (width as usize).checked_mul(bpp as usize)
+        .and_then(|n| n.checked_add(31))
+        .ok_or_else(too_large)? / 32 * 4;
+    let pixels_end = stride.checked_mul(height as usize)
+        .and_then(|n| n.checked_add(pixels_offset))
+        .ok_or_else(too_large)?;
+    if pixels_end > dib.len() {
+        return Err(format!("Pixel data is truncated: {} bytes expected, {} found", pixels_end, dib.len()));
+    }
+    let pixels = &dib[pixels_offset..pixels_end];
+
+    let [r_mask, g_mask, b_mask] = masks;
+    let mut img = RgbImage::new(width, height);
+    for row in 0..height {
+        let y = if top_down { row } else { height - row - 1 };
+        let line = &pixels[(row as usize * stride)..((row + 1) as usize * stride)];
+        for x in 0..width {
+            let p = &line[(x as usize * bytes_per_pixel)..((x as usize + 1) * bytes_per_pixel)];
+            let value = if bytes_per_pixel == 3 {
+                u32::from_le_bytes([p[0], p[1], p[2], 0])
+            } else {
+                u32::from_le_bytes([p[0], p[1], p[2], p[3]])
+            };
+            img.set_pixel(x, y, px!(channel(value, r_mask), channel(value, g_mask), channel(value, b_mask)));
+        }
+    }
+    return Ok(img);
+}
+
+/// Extracts masked channel and scales it to 8 bits
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    let v = (value & mask) >> shift;
+    return if max == 0xFF {
+        v as u8
+    } else {
+        (v as u64 * 255 / max as u64) as u8
+    };
+}
+
+fn read_bytes<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], String> {
+    bytes.get(offset..(offset + N))
+        .map(|b| b.try_into().unwrap())
+        .ok_or_else(|| format!("Unexpected end of DIB data at {}", offset))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    read_bytes(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    read_bytes(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> Result<i32, String> {
+    read_bytes(bytes, offset).map(i32::from_le_bytes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bmp::{Pixel, px};
+
+    use crate::dib::{channel, decode};
+
+    // 2x2 image:
+    // red   green
+    // blue  white
+    const EXPECTED: [[Pixel; 2]; 2] = [
+        [px!(255, 0, 0), px!(0, 255, 0)],
+        [px!(0, 0, 255), px!(255, 255, 255)],
+    ];
+
+    fn info_header(header_size: u32, height: i32, bpp: u16, compression: u32) -> Vec<u8> {
+        let mut h = Vec::new();
+        h.extend(header_size.to_le_bytes());
+        h.extend(2i32.to_le_bytes()); // width
+        h.extend(height.to_le_bytes());
+        h.extend(1u16.to_le_bytes()); // planes
+        h.extend(bpp.to_le_bytes());
+        h.extend(compression.to_le_bytes());
+        h.extend([0u8; 20]); // image size, resolution, colors
+        return h;
+    }
+
+    fn pixels_24(top_down: bool) -> Vec<u8> {
+        // BGR, each row is padded to 4 bytes
+        let top = vec![0, 0, 255, 0, 255, 0, 0, 0];
+        let bottom = vec![255, 0, 0, 255, 255, 255, 0, 0];
+        return if top_down { [top, bottom].concat() } else { [bottom, top].concat() };
+    }
+
+    fn pixels_32(top_down: bool, encode: fn(u8, u8, u8) -> [u8; 4]) -> Vec<u8> {
+        let mut top = Vec::new();
+        top.extend(encode(255, 0, 0));
+        top.extend(encode(0, 255, 0));
+        let mut bottom = Vec::new();
+        bottom.extend(encode(0, 0, 255));
+        bottom.extend(encode(255, 255, 255));
+        return if top_down { [top, bottom].concat() } else { [bottom, top].concat() };
+    }
+
+    fn bgra(r: u8, g: u8, b: u8) -> [u8; 4] {
+        [b, g, r, 0xFF]
+    }
+
+    fn with_file_header(dib: Vec<u8>, pixels_offset: u32) -> Vec<u8> {
+        let mut f = Vec::new();
+        f.extend(b"BM");
+        f.extend((14 + dib.len() as u32).to_le_bytes());
+        f.extend([0u8; 4]);
+        f.extend((14 + pixels_offset).to_le_bytes());
+        f.extend(dib);
+        return f;
+    }
+
+    fn assert_expected(bytes: &[u8]) {
+        let img = decode(bytes).unwrap();
+        assert_eq!(2, img.width());
+        assert_eq!(2, img.height());
+        for y in 0..2 {
+            for x in 0..2 {
+                assert_eq!(EXPECTED[y][x], img.pixel(x as u32, y as u32), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_24_bottom_up() {
+        let dib = [info_header(40, 2, 24, 0), pixels_24(false)].concat();
+        assert_expected(&dib);
+        assert_expected(&with_file_header(dib, 40));
+    }
+
+    #[test]
+    fn test_decode_24_top_down() {
+        let dib = [info_header(40, -2, 24, 0), pixels_24(true)].concat();
+        assert_expected(&dib);
+        assert_expected(&with_file_header(dib, 40));
+    }
+
+    #[test]
+    fn test_decode_32_bottom_up() {
+        let dib = [info_header(40, 2, 32, 0), pixels_32(false, bgra)].concat();
+        assert_expected(&dib);
+        assert_expected(&with_file_header(dib, 40));
+    }
+
+    #[test]
+    fn test_decode_32_top_down() {
+        let dib = [info_header(40, -2, 32, 0), pixels_32(true, bgra)].concat();
+        assert_expected(&dib);
+    }
+
+    #[test]
+    fn test_decode_32_bitfields() {
+        // RGBA byte order: r=0x000000FF, g=0x0000FF00, b=0x00FF0000
+        let rgba = |r, g, b| [r, g, b, 0xFF];
+        let masks = [0x000000FFu32, 0x0000FF00, 0x00FF0000].iter().flat_map(|m| m.to_le_bytes()).collect::<Vec<_>>();
+        let dib = [info_header(40, 2, 32, 3), masks, pixels_32(false, rgba)].concat();
+        assert_expected(&dib);
+        assert_expected(&with_file_header(dib, 52));
+    }
+
+    #[test]
+    fn test_decode_v5_header() {
+        // BITMAPV5HEADER with masks inside header
+        let mut header = info_header(124, -2, 32, 3);
+        for m in [0x00FF0000u32, 0x0000FF00, 0x000000FF, 0xFF000000] {
+            header.extend(m.to_le_bytes());
+        }
+        header.resize(124, 0);
+        let dib = [header, pixels_32(true, bgra)].concat();
+        assert_expected(&dib);
+        assert_expected(&with_file_header(dib, 124));
+    }
+
+    #[test]
+    fn test_decode_file_header_offset() {
+        // pixel data is not right after the header
+        let dib = [info_header(40, 2, 24, 0), vec![0xAA; 6], pixels_24(false)].concat();
+        assert_expected(&with_file_header(dib, 46));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(b"BM").is_err());
+
+        // 8 bits per pixel
+        let dib = [info_header(40, 2, 8, 0), vec![0; 16]].concat();
+        assert!(decode(&dib).is_err());
+
+        // RLE compression
+        let dib = [info_header(40, 2, 24, 1), pixels_24(false)].concat();
+        assert!(decode(&dib).is_err());
+
+        // truncated pixels
+        let dib = [info_header(40, 2, 24, 0), vec![0; 10]].concat();
+        assert!(decode(&dib).is_err());
+
+        // huge dimensions
+        let mut dib = [info_header(40, 2, 32, 0), vec![0; 16]].concat();
+        dib[4..8].copy_from_slice(&i32::MAX.to_le_bytes());
+        dib[8..12].copy_from_slice(&i32::MIN.to_le_bytes());
+        assert!(decode(&dib).is_err());
+    }
+
+    #[test]
+    fn test_decode_template() {
+        let bytes = include_bytes!("template.bmp");
+        let expected = bmp::from_reader(&mut bytes.as_ref()).unwrap();
+        let img = decode(bytes).unwrap();
+        assert_eq!(expected.get_width(), img.width());
+        assert_eq!(expected.get_height(), img.height());
+        for (x, y) in expected.coordinates() {
+            assert_eq!(expected.get_pixel(x, y), img.pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn test_channel() {
+        assert_eq!(0xAB, channel(0x00AB0000, 0x00FF0000));
+        assert_eq!(0, channel(0x00AB0000, 0));
+        // 5 bits channel is scaled
+        assert_eq!(255, channel(0b11111 << 10, 0b11111 << 10));
+        assert_eq!(0, channel(0, 0b11111 << 10));
+    }
+}
@@ -3,78 +3,244 @@ use std::path::Path;
 use bmp::{Image, Pixel, px};
 use clipboard_win::{formats, get_clipboard};
 
-pub fn load_img_from_clipboard() -> Option<Image> {
-    return if let Ok(bytes) = get_clipboard(formats::Bitmap) {
-        if bytes[0x1C] == 32 {
-            // 32 bits per pixel :(
-            // `bmp` crate does not support it. I don't want to include other crates.
-            // convert it to 24 bits per pixel, ignore alpha channel
-            let width = (bytes[0x12] as u32) | ((bytes[0x13] as u32) << 8);
-            let height = (bytes[0x16] as u32) | ((bytes[0x17] as u32) << 8);
-
-            let mut img = Image::new(width, height);
-            for y in 0..height {
-                for x in 0..width {
-                    let b = bytes[(0x36 + (x + y * width) * 4 + 0) as usize];
-                    let g = bytes[(0x36 + (x + y * width) * 4 + 1) as usize];
-                    let r = bytes[(0x36 + (x + y * width) * 4 + 2) as usize];
-                    img.set_pixel(x, height - y - 1, px![r, g, b]);
-                }
-            }
-            Some(img)
-        } else {
-            Some(bmp::from_reader(&mut &bytes[..]).unwrap())
-        }
+use crate::{dib, png};
+use crate::color::ColorFilter;
+
+// alignment search of `GrayImage::template_match_ncc`
+const NCC_MAX_OFFSET: u32 = 2;
+const NCC_SCALES: [f64; 3] = [0.9, 1.0, 1.1];
+
+pub fn load_img_from_clipboard() -> Result<RgbImage, String> {
+    let bytes = get_clipboard(formats::Bitmap).map_err(|_| "Clipboard has no image data".to_owned())?;
+    return dib::decode(&bytes).map_err(|e| format!("Clipboard image was not decoded: {}", e));
+}
+
+/// Detects image format by magic bytes. Supports BMP and PNG.
+/// 24/32 bits per pixel BMP is decoded by `dib`, other bit depths by `bmp` crate
+pub fn load_img_from_file<P: AsRef<Path>>(path: P) -> Result<RgbImage, String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|e| format!("{} was not read: {}", path.display(), e))?;
+    return if bytes.starts_with(&png::SIGNATURE) {
+        png::decode(&bytes)
+    } else if bytes.starts_with(b"BM") {
+        dib::decode(&bytes).or_else(|e| {
+            bmp::from_reader(&mut bytes.as_slice())
+                .map(|img| RgbImage::from(&img))
+                .map_err(|_| e)
+        })
     } else {
-        None
+        Err(format!("{} has unsupported image format", path.display()))
     };
 }
 
-pub fn load_img_from_file<P: AsRef<Path>>(path: P) -> Image {
-    return bmp::open(path).unwrap();
+/// Loads glyph image of a template pack. Pixels with any channel brighter than 50% transform to 255, others are 0
+pub fn load_mask_from_file<P: AsRef<Path>>(path: P) -> Result<GrayImage, String> {
+    let img = load_img_from_file(path)?;
+    let glyph = ColorFilter::Hsv { hue: (0.0, 360.0), saturation: (0.0, 1.0), value: (0.5, 1.0) };
+    return Ok(GrayImage::filter_by(&img, &glyph, 0, 0, img.width(), img.height()));
+}
+
+/// Packed RGB image. Rows are stored top-down without padding, 3 bytes per pixel
+#[derive(Clone)]
+pub struct RgbImage {
+    w: u32,
+    h: u32,
+    data: Vec<u8>,
+}
+
+impl RgbImage {
+    /// Black image
+    pub fn new(w: u32, h: u32) -> Self {
+        Self { w, h, data: vec![0; Self::data_len(w, h)] }
+    }
+
+    /// `data` is packed RGB pixels, top-down
+    pub fn from_rgb(w: u32, h: u32, data: Vec<u8>) -> Self {
+        assert_eq!(Self::data_len(w, h), data.len());
+        Self { w, h, data }
+    }
+
+    /// Converts raw capture buffer with 4 bytes per pixel in BGRA order (GDI/DXGI).
+    /// Each row is `stride` bytes long. Alpha channel is ignored
+    pub fn from_bgra(w: u32, h: u32, stride: usize, top_down: bool, bytes: &[u8]) -> Result<Self, String> {
+        if stride < w as usize * 4 || bytes.len() < stride * h as usize {
+            return Err(format!("Capture buffer is too small for {}x{} image", w, h));
+        }
+        let mut data = Vec::with_capacity(Self::data_len(w, h));
+        for y in 0..h as usize {
+            let row = if top_down { y } else { h as usize - y - 1 };
+            for p in bytes[(row * stride)..(row * stride + w as usize * 4)].chunks_exact(4) {
+                data.extend_from_slice(&[p[2], p[1], p[0]]);
+            }
+        }
+        return Ok(Self { w, h, data });
+    }
+
+    /// Byte count of `w`x`h` image. Panics if it does not fit in memory
+    fn data_len(w: u32, h: u32) -> usize {
+        (w as usize).checked_mul(h as usize)
+            .and_then(|n| n.checked_mul(3))
+            .unwrap_or_else(|| panic!("{}x{} image is too large", w, h))
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> Pixel {
+        let i = (y as usize * self.w as usize + x as usize) * 3;
+        px!(self.data[i], self.data[i + 1], self.data[i + 2])
+    }
+
+    #[inline]
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: Pixel) {
+        let i = (y as usize * self.w as usize + x as usize) * 3;
+        self.data[i..(i + 3)].copy_from_slice(&[color.r, color.g, color.b]);
+    }
+
+    /// Cheap crop without copying. `right` and `bottom` are exclusive
+    pub fn view(&self, left: u32, top: u32, right: u32, bottom: u32) -> RgbView<'_> {
+        RgbView { img: self, left: 0, top: 0, w: self.w, h: self.h }.view(left, top, right, bottom)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        Image::from(self).save(path)
+    }
 }
 
+impl From<&Image> for RgbImage {
+    fn from(src: &Image) -> Self {
+        let mut dest = RgbImage::new(src.get_width(), src.get_height());
+        for (x, y) in src.coordinates() {
+            dest.set_pixel(x, y, src.get_pixel(x, y));
+        }
+        return dest;
+    }
+}
+
+impl From<&RgbImage> for Image {
+    fn from(src: &RgbImage) -> Self {
+        let mut dest = Image::new(src.w, src.h);
+        for (x, y) in dest.coordinates() {
+            dest.set_pixel(x, y, src.pixel(x, y));
+        }
+        return dest;
+    }
+}
+
+/// Rectangle area of `RgbImage`
+#[derive(Clone, Copy)]
+pub struct RgbView<'a> {
+    img: &'a RgbImage,
+    left: u32,
+    top: u32,
+    w: u32,
+    h: u32,
+}
+
+impl<'a> RgbView<'a> {
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> Pixel {
+        debug_assert!(x < self.w && y < self.h);
+        self.img.pixel(self.left + x, self.top + y)
+    }
+
+    /// Packed RGB pixels of view row `y`
+    #[inline]
+    pub fn row(&self, y: u32) -> &'a [u8] {
+        debug_assert!(y < self.h);
+        let start = ((self.top + y) as usize * self.img.w as usize + self.left as usize) * 3;
+        &self.img.data[start..(start + self.w as usize * 3)]
+    }
+
+    /// Nested crop. Coordinates are relative to this view, `right` and `bottom` are exclusive
+    pub fn view(&self, left: u32, top: u32, right: u32, bottom: u32) -> RgbView<'a> {
+        assert!(left <= right && right <= self.w, "bad view width");
+        assert!(top <= bottom && bottom <= self.h, "bad view height");
+        RgbView { img: self.img, left: self.left + left, top: self.top + top, w: right - left, h: bottom - top }
+    }
+}
+
+/// Binary mask (0 or 255) with summed-area table of non-zero pixels,
+/// so rectangle queries are O(1)
 pub struct GrayImage {
     w: u32,
     h: u32,
     data: Vec<u8>,
+    // `(w + 1) * (h + 1)` table. `sums[y][x]` is count of non-zero pixels in `0..x` x `0..y` area
+    sums: Vec<u32>,
 }
 
 impl GrayImage {
+    pub fn new(w: u32, h: u32, data: Vec<u8>) -> Self {
+        assert_eq!((w * h) as usize, data.len());
+
+        let stride = (w + 1) as usize;
+        let mut sums = vec![0u32; stride * (h + 1) as usize];
+        for y in 0..h as usize {
+            let mut row_sum = 0;
+            for x in 0..w as usize {
+                row_sum += (data[y * w as usize + x] != 0) as u32;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+            }
+        }
+        return Self { w, h, data, sums };
+    }
+
     /// Target `color` transforms to 255, other colors are 0
-    pub fn filter(img: &Image, color: &Pixel, threshold: u8, left: u32, top: u32, right: u32, bottom: u32) -> Self {
+    pub fn filter(img: &RgbImage, color: &Pixel, threshold: u8, left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        Self::filter_by(img, &ColorFilter::Rgb { color: *color, threshold }, left, top, right, bottom)
+    }
+
+    /// Pixels matched by `filter` transform to 255, others are 0
+    pub fn filter_by(img: &RgbImage, filter: &ColorFilter, left: u32, top: u32, right: u32, bottom: u32) -> Self {
         debug_assert!(right > left);
         debug_assert!(bottom > top);
 
-        let r_min = i16::max(color.r as i16 - threshold as i16, 0) as u8;
-        let g_min = i16::max(color.g as i16 - threshold as i16, 0) as u8;
-        let b_min = i16::max(color.b as i16 - threshold as i16, 0) as u8;
-        let color_min = px!(r_min, g_min, b_min);
+        let matches = filter.matcher();
+        let view = img.view(left, top, right, bottom);
+        let (w, h) = (view.width(), view.height());
+        let mut data = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            for p in view.row(y).chunks_exact(3) {
+                data.push(if matches(p[0], p[1], p[2]) { 255u8 } else { 0u8 });
+            }
+        }
+        debug_assert_eq!(w * h, data.len() as u32);
+        return Self::new(w, h, data);
+    }
 
-        let r_max = i16::min(color.r as i16 + threshold as i16, 255) as u8;
-        let g_max = i16::min(color.g as i16 + threshold as i16, 255) as u8;
-        let b_max = i16::min(color.b as i16 + threshold as i16, 255) as u8;
-        let color_max = px!(r_max, g_max, b_max);
+    /// Luma (BT.601) of each pixel. Keeps anti-aliasing, which `filter_by` thresholds away
+    pub fn luma(img: &RgbImage, left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        debug_assert!(right > left);
+        debug_assert!(bottom > top);
 
-        let w = right - left;
-        let h = bottom - top;
+        let view = img.view(left, top, right, bottom);
+        let (w, h) = (view.width(), view.height());
         let mut data = Vec::with_capacity((w * h) as usize);
         for y in 0..h {
-            for x in 0..w {
-                let p1 = img.get_pixel(x + left, y + top);
-
-                let v = if p1.r >= color_min.r && p1.r <= color_max.r &&
-                    p1.g >= color_min.g && p1.g <= color_max.g &&
-                    p1.b >= color_min.b && p1.b <= color_max.b {
-                    255u8
-                } else {
-                    0u8
-                };
-                data.push(v);
+            for p in view.row(y).chunks_exact(3) {
+                data.push(((299 * p[0] as u32 + 587 * p[1] as u32 + 114 * p[2] as u32 + 500) / 1000) as u8);
             }
         }
-        debug_assert_eq!(w * h, data.len() as u32);
-        return Self { w, h, data };
+        return Self::new(w, h, data);
     }
 
     #[inline]
@@ -92,39 +258,60 @@ impl GrayImage {
         self.data[(y * self.w + x) as usize]
     }
 
+    /// Nearest-neighbor resize, each side is multiplied by `scale`
+    pub fn scaled(&self, scale: f64) -> GrayImage {
+        let w = u32::max(1, (self.w as f64 * scale).round() as u32);
+        let h = u32::max(1, (self.h as f64 * scale).round() as u32);
+        let mut data = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            let src_y = u32::min((y as f64 / scale) as u32, self.h - 1);
+            for x in 0..w {
+                let src_x = u32::min((x as f64 / scale) as u32, self.w - 1);
+                data.push(self.pixel(src_x, src_y));
+            }
+        }
+        return GrayImage::new(w, h, data);
+    }
+
+    /// Count of non-zero pixels in `left..right` x `top..bottom` area
+    #[inline]
+    pub fn count(&self, left: u32, top: u32, right: u32, bottom: u32) -> u32 {
+        debug_assert!(left <= right && right <= self.w);
+        debug_assert!(top <= bottom && bottom <= self.h);
+        let stride = (self.w + 1) as usize;
+        let at = |x: u32, y: u32| self.sums[y as usize * stride + x as usize];
+        at(right, bottom) + at(left, top) - at(left, bottom) - at(right, top)
+    }
+
     /// Tries to find filled rectangle in reverse mode (the most bottom-right)
     pub fn rfind_rect(&self, rect_width: u32, rect_height: u32) -> Option<(u32, u32)> {
         if self.h < rect_height || self.w < rect_width || rect_width == 0 || rect_height == 0 {
             return None;
         }
+        let area = rect_width * rect_height;
         (0..=(self.h - rect_height)).rev().find_map(|y_start| {
-            (0..=(self.w - rect_width)).rev().find(|x_start| {
-                let is_rect = (0..rect_height).all(|dy| {
-                    (0..rect_width).all(|dx|
-                        self.pixel(x_start + dx, y_start + dy) != 0
-                    )
-                });
-                is_rect
-            }).map(|x_start| (x_start, y_start))
+            (0..=(self.w - rect_width)).rev()
+                .find(|&x_start| self.count(x_start, y_start, x_start + rect_width, y_start + rect_height) == area)
+                .map(|x_start| (x_start, y_start))
         })
     }
 
     /// `result[x] == true` means column `x` has at least 1 non-zero pixel
     pub fn columns_usage(&self) -> Vec<bool> {
-        (0..self.w).map(|x| {
-            (0..self.h).any(|y|
-                self.pixel(x, y) != 0
-            )
-        }).collect()
+        (0..self.w).map(|x| self.count(x, 0, x + 1, self.h) != 0).collect()
     }
 
     /// `result[y] == true` means row `y` has at least 1 non-zero pixel
     pub fn rows_usage(&self) -> Vec<bool> {
-        (0..self.h).map(|y| {
-            (0..self.w).any(|x|
-                self.pixel(x, y) != 0
-            )
-        }).collect()
+        (0..self.h).map(|y| self.count(0, y, self.w, y + 1) != 0).collect()
+    }
+
+    /// Copies `self_*` area. `self_right` and `self_bottom` are inclusive like `rect_hull` result
+    pub fn crop(&self, self_left: u32, self_top: u32, self_right: u32, self_bottom: u32) -> GrayImage {
+        let data = (self_top..=self_bottom)
+            .flat_map(|y| (self_left..=self_right).map(move |x| self.pixel(x, y)))
+            .collect();
+        GrayImage::new(self_right - self_left + 1, self_bottom - self_top + 1, data)
     }
 
     /// Returns the smallest rectangle, which contains all non-zero pixels in `self_*` area
@@ -134,24 +321,16 @@ impl GrayImage {
         debug_assert!(self_right < self.w);
         debug_assert!(self_bottom < self.h);
 
-        let mut result: Option<(u32, u32, u32, u32)> = None;
-
-        for y in self_top..=self_bottom {
-            for x in self_left..=self_right {
-                if self.pixel(x, y) != 0 {
-                    let prev = result.take()
-                        .unwrap_or((u32::max_value(), u32::max_value(), u32::min_value(), u32::min_value()));
-                    result = Some((
-                        u32::min(x, prev.0),
-                        u32::min(y, prev.1),
-                        u32::max(x, prev.2),
-                        y,
-                    ));
-                }
-            }
+        let (right, bottom) = (self_right + 1, self_bottom + 1);
+        if self.count(self_left, self_top, right, bottom) == 0 {
+            return None;
         }
 
-        return result;
+        let top = (self_top..bottom).find(|&y| self.count(self_left, y, right, y + 1) != 0).unwrap();
+        let bottom = (top..bottom).rev().find(|&y| self.count(self_left, y, right, y + 1) != 0).unwrap();
+        let left = (self_left..right).find(|&x| self.count(x, top, x + 1, bottom + 1) != 0).unwrap();
+        let right = (left..right).rev().find(|&x| self.count(x, top, x + 1, bottom + 1) != 0).unwrap();
+        return Some((left, top, right, bottom));
     }
 
     pub fn template_match_error_score(&self, self_left: u32, self_top: u32, self_right: u32, self_bottom: u32, template: &GrayImage) -> f64 {
@@ -162,6 +341,10 @@ impl GrayImage {
 
         let self_width = self_right - self_left;
         let self_height = self_bottom - self_top;
+        // one pixel wide or tall area (e.g. speck) can't be scaled to template
+        if self_width == 0 || self_height == 0 {
+            return f64::INFINITY;
+        }
         let self_pixel = |x: u32, y: u32| -> u8 {
             self.pixel(self_left + x, self_top + y)
         };
@@ -181,9 +364,74 @@ impl GrayImage {
         }).sum();
         return error as f64 / (self_width * self_height) as f64;
     }
+
+    /// Normalized cross-correlation (`-1..=1`, 1 is the best) of `self_*` area (inclusive) and `template`.
+    /// Uses pixel intensities, so anti-aliased grayscale data is fine. Area is shifted by `±NCC_MAX_OFFSET` px
+    /// and scaled by `NCC_SCALES` to tolerate inaccurate hull; the best alignment is returned
+    pub fn template_match_ncc(&self, self_left: u32, self_top: u32, self_right: u32, self_bottom: u32, template: &GrayImage) -> f64 {
+        debug_assert!(self_right >= self_left);
+        debug_assert!(self_bottom >= self_top);
+
+        let width = (self_right - self_left + 1) as f64;
+        let height = (self_bottom - self_top + 1) as f64;
+        let center = (self_left as f64 + width / 2.0, self_top as f64 + height / 2.0);
+        let offset = NCC_MAX_OFFSET as i32;
+
+        let mut best = -1f64;
+        for scale in NCC_SCALES {
+            for dy in -offset..=offset {
+                for dx in -offset..=offset {
+                    let (w, h) = (width * scale, height * scale);
+                    let left = center.0 + dx as f64 - w / 2.0;
+                    let top = center.1 + dy as f64 - h / 2.0;
+                    best = best.max(self.ncc_at(left, top, w / template.w as f64, h / template.h as f64, template));
+                }
+            }
+        }
+        return best;
+    }
+
+    /// NCC of `template` and area at (`left`, `top`) scaled by `x_step`/`y_step` per template pixel
+    fn ncc_at(&self, left: f64, top: f64, x_step: f64, y_step: f64, template: &GrayImage) -> f64 {
+        let n = (template.w * template.h) as f64;
+        let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0f64, 0f64, 0f64, 0f64, 0f64);
+        for ty in 0..template.h {
+            for tx in 0..template.w {
+                let a = self.sample(left + (tx as f64 + 0.5) * x_step - 0.5, top + (ty as f64 + 0.5) * y_step - 0.5);
+                let b = template.pixel(tx, ty) as f64;
+                sum_a += a;
+                sum_b += b;
+                sum_aa += a * a;
+                sum_bb += b * b;
+                sum_ab += a * b;
+            }
+        }
+        let var_a = sum_aa - sum_a * sum_a / n;
+        let var_b = sum_bb - sum_b * sum_b / n;
+        if var_a <= f64::EPSILON || var_b <= f64::EPSILON {
+            return 0.0;
+        }
+        return (sum_ab - sum_a * sum_b / n) / (var_a * var_b).sqrt();
+    }
+
+    /// Bilinear interpolation. Pixels outside the image are 0
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let at = |x: f64, y: f64| -> f64 {
+            if x < 0.0 || y < 0.0 || x >= self.w as f64 || y >= self.h as f64 {
+                0.0
+            } else {
+                self.pixel(x as u32, y as u32) as f64
+            }
+        };
+        let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1.0, y0) * fx;
+        let bottom = at(x0, y0 + 1.0) * (1.0 - fx) + at(x0 + 1.0, y0 + 1.0) * fx;
+        return top * (1.0 - fy) + bottom * fy;
+    }
 }
 
-#[cfg(test)]
+/// Converts `GrayImage` to black-white `Image`, so it may be saved as bmp
 pub fn into_image(src: &GrayImage) -> Image {
     let mut dest = Image::new(src.w, src.h);
     for (x, y) in dest.coordinates() {
@@ -197,11 +445,11 @@ pub fn into_image(src: &GrayImage) -> Image {
 mod tests {
     use bmp::{Pixel, px};
 
-    use crate::img::{GrayImage, into_image};
+    use crate::img::{GrayImage, into_image, load_img_from_file, RgbImage};
 
     #[test]
     fn test_filter() {
-        let mut img = bmp::Image::new(4, 4);
+        let mut img = RgbImage::new(4, 4);
         img.set_pixel(0, 0, px!(0, 10, 20));
         img.set_pixel(1, 0, px!(10, 20, 30));
         img.set_pixel(0, 1, px!(15, 25, 35));
@@ -217,6 +465,69 @@ mod tests {
         assert_eq!(filtered.pixel(1, 1), 0);
     }
 
+    #[test]
+    fn test_luma() {
+        let mut img = RgbImage::new(3, 2);
+        img.set_pixel(1, 0, px!(255, 255, 255));
+        img.set_pixel(2, 0, px!(0, 255, 0));
+        img.set_pixel(1, 1, px!(208, 236, 88));
+
+        let luma = GrayImage::luma(&img, 1, 0, 3, 2);
+        assert_eq!(vec![255, 150, 211, 0], luma.data);
+    }
+
+    #[test]
+    fn test_rgb_image() {
+        let mut img = RgbImage::new(3, 2);
+        img.set_pixel(2, 1, px!(1, 2, 3));
+        assert_eq!(3, img.width());
+        assert_eq!(2, img.height());
+        assert_eq!(px!(1, 2, 3), img.pixel(2, 1));
+        assert_eq!(px!(0, 0, 0), img.pixel(1, 1));
+
+        let bmp = bmp::Image::from(&img);
+        assert_eq!(px!(1, 2, 3), bmp.get_pixel(2, 1));
+        let back = RgbImage::from(&bmp);
+        assert_eq!(px!(1, 2, 3), back.pixel(2, 1));
+    }
+
+    #[test]
+    fn test_rgb_view() {
+        let data = (0..(4 * 3 * 3)).map(|v| v as u8).collect();
+        let img = RgbImage::from_rgb(4, 3, data);
+
+        let view = img.view(1, 1, 3, 3);
+        assert_eq!(2, view.width());
+        assert_eq!(2, view.height());
+        assert_eq!(img.pixel(1, 1), view.pixel(0, 0));
+        assert_eq!(img.pixel(2, 2), view.pixel(1, 1));
+        assert_eq!(&[15, 16, 17, 18, 19, 20], view.row(0));
+
+        let nested = view.view(1, 0, 2, 2);
+        assert_eq!(1, nested.width());
+        assert_eq!(img.pixel(2, 2), nested.pixel(0, 1));
+    }
+
+    #[test]
+    fn test_from_bgra() {
+        // 2x2, bottom-up, 12 bytes stride
+        let bytes = [
+            3, 2, 1, 0, 6, 5, 4, 0, 9, 9, 9, 9,
+            30, 20, 10, 0, 60, 50, 40, 0, 9, 9, 9, 9,
+        ];
+        let img = RgbImage::from_bgra(2, 2, 12, false, &bytes).unwrap();
+        assert_eq!(px!(10, 20, 30), img.pixel(0, 0));
+        assert_eq!(px!(40, 50, 60), img.pixel(1, 0));
+        assert_eq!(px!(1, 2, 3), img.pixel(0, 1));
+        assert_eq!(px!(4, 5, 6), img.pixel(1, 1));
+
+        let img = RgbImage::from_bgra(2, 2, 12, true, &bytes).unwrap();
+        assert_eq!(px!(1, 2, 3), img.pixel(0, 0));
+
+        assert!(RgbImage::from_bgra(2, 2, 4, true, &bytes).is_err());
+        assert!(RgbImage::from_bgra(2, 3, 12, true, &bytes).is_err());
+    }
+
     #[test]
     fn test_rfind_rect() {
         let pixels = vec![
@@ -259,6 +570,20 @@ mod tests {
         assert_eq!(3, usage.capacity());
     }
 
+    #[test]
+    fn test_crop() {
+        let img = GrayImage::new(3, 3, vec![
+            1, 2, 3,
+            4, 5, 6,
+            7, 8, 9,
+        ]);
+        let cropped = img.crop(1, 1, 2, 2);
+        assert_eq!(2, cropped.width());
+        assert_eq!(2, cropped.height());
+        assert_eq!(5, cropped.pixel(0, 0));
+        assert_eq!(9, cropped.pixel(1, 1));
+    }
+
     #[test]
     fn test_rect_hull() {
         let pixels = vec![
@@ -334,6 +659,81 @@ mod tests {
         let half_error = into_img(half_match, 4, 4).template_match_error_score(0, 0, 3, 3, &template);
         assert!(0.43 < half_error);
         assert!(0.58 > half_error);
+
+        let speck = vec![
+            000, 000, 000, 000,
+            000, 255, 000, 000,
+            000, 255, 000, 000,
+            000, 000, 000, 000,
+        ];
+        assert_eq!(f64::INFINITY, into_img(speck.clone(), 4, 4).template_match_error_score(1, 1, 1, 1, &template));
+        assert_eq!(f64::INFINITY, into_img(speck, 4, 4).template_match_error_score(1, 1, 1, 2, &template));
+    }
+
+    #[test]
+    fn test_template_match_ncc() {
+        let template = into_img(vec![
+            000, 000, 000, 000,
+            000, 255, 255, 000,
+            000, 255, 255, 000,
+            000, 255, 000, 000,
+            000, 000, 000, 000,
+        ], 4, 5);
+
+        let exact = into_img(vec![
+            000, 000, 000, 000, 000, 000,
+            000, 000, 000, 000, 000, 000,
+            000, 000, 255, 255, 000, 000,
+            000, 000, 255, 255, 000, 000,
+            000, 000, 255, 000, 000, 000,
+            000, 000, 000, 000, 000, 000,
+            000, 000, 000, 000, 000, 000,
+        ], 6, 7);
+        assert!(exact.template_match_ncc(1, 1, 4, 5, &template) > 0.99);
+        // hull is 1 px off, offset search compensates it
+        assert!(exact.template_match_ncc(2, 1, 5, 5, &template) > 0.99);
+        assert!(exact.template_match_error_score(2, 1, 5, 5, &template) > 0.0);
+
+        // anti-aliased grayscale glyph
+        let soft = into_img(vec![
+            000, 000, 000, 000, 000, 000,
+            000, 000, 030, 030, 000, 000,
+            000, 040, 230, 220, 060, 000,
+            000, 050, 240, 200, 040, 000,
+            000, 040, 210, 060, 000, 000,
+            000, 000, 030, 000, 000, 000,
+            000, 000, 000, 000, 000, 000,
+        ], 6, 7);
+        assert!(soft.template_match_ncc(1, 1, 4, 5, &template) > 0.85);
+
+        let other = into_img(vec![
+            000, 000, 000, 000, 000, 000,
+            000, 000, 000, 000, 000, 000,
+            000, 000, 000, 255, 000, 000,
+            000, 000, 255, 000, 000, 000,
+            000, 000, 000, 255, 000, 000,
+            000, 000, 000, 000, 000, 000,
+            000, 000, 000, 000, 000, 000,
+        ], 6, 7);
+        assert!(other.template_match_ncc(1, 1, 4, 5, &template) < 0.7);
+
+        let blank = into_img(vec![0; 6 * 7], 6, 7);
+        assert_eq!(0f64, blank.template_match_ncc(1, 1, 4, 5, &template));
+    }
+
+    #[test]
+    fn test_scaled() {
+        let img = into_img(vec![
+            255, 000,
+            000, 255,
+        ], 2, 2);
+        let big = img.scaled(2.0);
+        assert_eq!(4, big.width());
+        assert_eq!(4, big.height());
+        assert_eq!(vec![255, 255, 0, 0, 255, 255, 0, 0, 0, 0, 255, 255, 0, 0, 255, 255], big.data);
+
+        let small = big.scaled(0.5);
+        assert_eq!(img.data, small.data);
     }
 
     #[test]
@@ -353,8 +753,122 @@ mod tests {
 
     }
 
+    #[test]
+    #[should_panic(expected = "too large")]
+    fn test_rgb_image_too_large() {
+        RgbImage::new(u32::MAX, u32::MAX);
+    }
+
+    #[test]
+    fn test_load_img_from_file() {
+        let path = std::env::temp_dir().join("cyberbot2077_test_load_img.bmp");
+        let mut img = bmp::Image::new(2, 1);
+        img.set_pixel(1, 0, px!(10, 20, 30));
+        img.save(&path).unwrap();
+        let loaded = load_img_from_file(&path).unwrap();
+        assert_eq!(px!(10, 20, 30), loaded.pixel(1, 0));
+
+        // 8 bits per pixel with palette, index 1 is (10, 20, 30)
+        let mut palette = vec![0; 256 * 4];
+        palette[4..8].copy_from_slice(&[30, 20, 10, 0]);
+        let pixels_offset = 14 + 40 + palette.len() as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&(pixels_offset + 4).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&pixels_offset.to_le_bytes());
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        bytes.extend_from_slice(&1i32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8u16.to_le_bytes());
+        bytes.extend_from_slice(&[0; 24]);
+        bytes.extend_from_slice(&palette);
+        bytes.extend_from_slice(&[0, 1, 0, 0]);
+        std::fs::write(&path, &bytes).unwrap();
+        let loaded = load_img_from_file(&path).unwrap();
+        assert_eq!(px!(0, 0, 0), loaded.pixel(0, 0));
+        assert_eq!(px!(10, 20, 30), loaded.pixel(1, 0));
+
+        std::fs::write(&path, b"GIF89a").unwrap();
+        assert!(load_img_from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(load_img_from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_count() {
+        let pixels = vec![
+            255, 000, 255,
+            255, 255, 000,
+        ];
+
+        let img = into_img(pixels, 3, 2);
+        assert_eq!(4, img.count(0, 0, 3, 2));
+        assert_eq!(0, img.count(1, 1, 1, 2));
+        assert_eq!(2, img.count(0, 0, 1, 2));
+        assert_eq!(1, img.count(1, 0, 3, 1));
+        assert_eq!(1, img.count(1, 1, 3, 2));
+    }
+
+    #[test]
+    fn test_integral_queries_match_brute_force() {
+        // pseudo-random sparse mask
+        let (w, h) = (37, 23);
+        let mut seed = 12345u32;
+        let pixels = (0..(w * h)).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            if (seed >> 16) % 7 == 0 { 255 } else { 0 }
+        }).collect::<Vec<u8>>();
+        let img = into_img(pixels.clone(), w, h);
+        let pixel = |x: u32, y: u32| pixels[(y * w + x) as usize] != 0;
+
+        for (left, top, right, bottom) in [(0, 0, w - 1, h - 1), (3, 4, 20, 9), (10, 10, 10, 10), (5, 0, 6, 22)] {
+            let mut expected: Option<(u32, u32, u32, u32)> = None;
+            for y in top..=bottom {
+                for x in left..=right {
+                    if pixel(x, y) {
+                        expected = Some(match expected {
+                            None => (x, y, x, y),
+                            Some((l, t, r, _)) => (l.min(x), t, r.max(x), y),
+                        });
+                    }
+                }
+            }
+            assert_eq!(expected, img.rect_hull(left, top, right, bottom));
+        }
+
+        let expected_columns = (0..w).map(|x| (0..h).any(|y| pixel(x, y))).collect::<Vec<_>>();
+        assert_eq!(expected_columns, img.columns_usage());
+        let expected_rows = (0..h).map(|y| (0..w).any(|x| pixel(x, y))).collect::<Vec<_>>();
+        assert_eq!(expected_rows, img.rows_usage());
+    }
+
+    /// `cargo test --release -- --ignored bench_rfind_rect_4k --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_rfind_rect_4k() {
+        // 4K mask with a lot of noise and 300x5 bar at the top-left corner
+        let (w, h) = (3840, 2160);
+        let mut pixels = (0..(w * h)).map(|i| if (i * 7) % 11 < 9 { 255 } else { 0 }).collect::<Vec<u8>>();
+        for y in 100..105 {
+            for x in 100..400 {
+                pixels[(y * w + x) as usize] = 255;
+            }
+        }
+
+        let start = std::time::Instant::now();
+        let img = into_img(pixels, w, h);
+        let built = start.elapsed();
+        let found = img.rfind_rect(300, 5);
+        let total = start.elapsed();
+
+        assert_eq!(Some((100, 100)), found);
+        println!("3840x2160: summed-area table {:?}, rfind_rect(300, 5) {:?}", built, total - built);
+    }
+
     fn into_img(data: Vec<u8>, w: u32, h: u32) -> GrayImage {
-        assert_eq!((w * h) as usize, data.len());
-        return GrayImage { data, w, h };
+        return GrayImage::new(w, h, data);
     }
 }
\ No newline at end of file
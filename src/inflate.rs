@@ -0,0 +1,269 @@
+// DEFLATE (RFC 1951) and zlib (RFC 1950) decompression. It is enough for PNG screenshots
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// order of code length codes in dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const MAX_BITS: usize = 15;
+
+/// Decompresses zlib stream. Adler-32 checksum is not verified
+pub fn zlib_decompress(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if bytes.len() < 2 {
+        return Err("zlib stream is too short".to_owned());
+    }
+    let (cmf, flg) = (bytes[0], bytes[1]);
+    if cmf & 0x0F != 8 || ((cmf as u16) << 8 | flg as u16) % 31 != 0 {
+        return Err("Bad zlib header".to_owned());
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib preset dictionary is not supported".to_owned());
+    }
+    return inflate(&bytes[2..]);
+}
+
+/// Decompresses raw DEFLATE stream
+pub fn inflate(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = BitReader { bytes, pos: 0, buf: 0, count: 0 };
+    let mut out = Vec::with_capacity(bytes.len() * 4);
+
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => stored_block(&mut bits, &mut out)?,
+            1 => {
+                let (literals, distances) = fixed_tables();
+                compressed_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut bits)?;
+                compressed_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("Bad DEFLATE block type".to_owned()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    /// Reads `n` bits (n <= 16), the least significant bit first
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.bytes.get(self.pos).ok_or_else(|| "Unexpected end of DEFLATE stream".to_owned())?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let v = self.buf & ((1u32 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        return Ok(v);
+    }
+
+    fn align_to_byte(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code: number of codes per length and symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        return Self { counts, symbols };
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, String> {
+        // codes of the same length are consecutive. `first` is the first code of current length
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err("Bad Huffman code".to_owned());
+    }
+}
+
+fn stored_block(bits: &mut BitReader, out: &mut Vec<u8>) -> Result<(), String> {
+    bits.align_to_byte();
+    let header = bits.bytes.get(bits.pos..(bits.pos + 4)).ok_or_else(|| "Unexpected end of stored block".to_owned())?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err("Bad stored block length".to_owned());
+    }
+    bits.pos += 4;
+
+    let data = bits.bytes.get(bits.pos..(bits.pos + len as usize)).ok_or_else(|| "Unexpected end of stored block".to_owned())?;
+    out.extend_from_slice(data);
+    bits.pos += len as usize;
+    return Ok(());
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    return (Huffman::new(&lengths), Huffman::new(&[5u8; 30]));
+}
+
+fn dynamic_tables(bits: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literals_count = bits.bits(5)? as usize + 257;
+    let distances_count = bits.bits(5)? as usize + 1;
+    let code_lengths_count = bits.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_lengths_count) {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literals_count + distances_count);
+    while lengths.len() < literals_count + distances_count {
+        let (value, repeat) = match code_lengths.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or_else(|| "Nothing to repeat in code lengths".to_owned())?;
+                (prev, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            18 => (0, 11 + bits.bits(7)?),
+            _ => return Err("Bad code length symbol".to_owned()),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != literals_count + distances_count {
+        return Err("Code lengths overflow".to_owned());
+    }
+
+    let literals = Huffman::new(&lengths[..literals_count]);
+    let distances = Huffman::new(&lengths[literals_count..]);
+    return Ok((literals, distances));
+}
+
+fn compressed_block(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err("Bad length symbol".to_owned());
+        }
+        let len = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+
+        let i = distances.decode(bits)? as usize;
+        if i >= DISTANCE_BASE.len() {
+            return Err("Bad distance symbol".to_owned());
+        }
+        let distance = DISTANCE_BASE[i] as usize + bits.bits(DISTANCE_EXTRA[i] as u32)? as usize;
+        if distance > out.len() {
+            return Err("Distance is too far back".to_owned());
+        }
+
+        // copied region may overlap with output
+        let start = out.len() - distance;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::inflate::{inflate, zlib_decompress};
+
+    #[test]
+    fn test_stored_block() {
+        let stream = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(Ok(b"abc".to_vec()), inflate(&stream));
+
+        // bad NLEN
+        let stream = [0x01, 0x03, 0x00, 0xFC, 0xFE, b'a', b'b', b'c'];
+        assert!(inflate(&stream).is_err());
+    }
+
+    #[test]
+    fn test_fixed_block() {
+        // zlib.compress(b"hello hello hello", 9)
+        let stream = [0x78, 0xDA, 0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x00, 0x3A, 0x2E, 0x06, 0x7D];
+        assert_eq!(Ok(b"hello hello hello".to_vec()), zlib_decompress(&stream));
+    }
+
+    #[test]
+    fn test_dynamic_block() {
+        let src = b"breach protocol matrix daemon";
+        let expected = (0..400usize).map(|i| src[(i * i / 7) % src.len()]).collect::<Vec<u8>>();
+
+        let stream = [
+            0x78, 0xDA, 0xD5, 0x8F, 0x41, 0xAA, 0x04, 0x30, 0x08, 0x43, 0xAF, 0xD2, 0xAB, 0x45, 0x2B, 0x54, 0xA8, 0x4D, 0x91, 0x2E,
+            0x3C, 0xFE, 0xF8, 0x3F, 0x73, 0x89, 0xC9, 0x4A, 0x79, 0x09, 0x46, 0x11, 0x49, 0xC3, 0xBA, 0xE4, 0x86, 0xE3, 0xE0, 0x2A,
+            0xC6, 0x59, 0x04, 0x8C, 0x30, 0xD5, 0xCA, 0xE7, 0xC9, 0xC2, 0xB6, 0xF4, 0x06, 0x6A, 0x0F, 0x9C, 0xC4, 0x0B, 0x4A, 0x68,
+            0x91, 0xB1, 0x7A, 0x1B, 0xEC, 0xD0, 0x3D, 0xCE, 0x71, 0x6A, 0x10, 0x1C, 0xD0, 0x54, 0x89, 0xD9, 0x09, 0xF2, 0x31, 0x6F,
+            0x2B, 0x7B, 0x68, 0xE0, 0x33, 0xA4, 0x21, 0xFE, 0x6D, 0x75, 0x06, 0xFD, 0xDC, 0xB6, 0x37, 0xE1, 0x5C, 0x41, 0x96, 0x86,
+            0x30, 0xBE, 0x47, 0x4C, 0x4F, 0x27, 0xD2, 0x36, 0x8A, 0xE9, 0x2F, 0x4B, 0xD5, 0x40, 0x03, 0xB8, 0x1A, 0xE8, 0xED, 0xBA,
+            0x8E, 0x4D, 0xDE, 0x05, 0x4B, 0xF9, 0xD3, 0xEF, 0xBF, 0xF2, 0x01, 0x9E, 0xBF, 0x9C, 0x7D,
+        ];
+        assert_eq!(Ok(expected), zlib_decompress(&stream));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(zlib_decompress(&[]).is_err());
+        assert!(zlib_decompress(&[0x78, 0x00]).is_err());
+        // truncated stream
+        assert!(zlib_decompress(&[0x78, 0xDA, 0xCB, 0x48, 0xCD]).is_err());
+        // reserved block type
+        assert!(inflate(&[0x07]).is_err());
+    }
+}
@@ -1,17 +1,425 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Acquire;
 use std::thread::sleep;
 use std::time::Duration;
 use winput::{Action, Button, Input, Mouse};
 
-pub fn click(dx: i32, dy: i32) {
-    Mouse::move_relative(dx, dy);
-    sleep(Duration::from_millis(300));
+use crate::monitor::ScreenMapping;
+use crate::pacing::{interpolate, Pacing, XorShift};
 
-    let input = Input::from_button(Button::Left, Action::Press);
-    winput::send_inputs(&[input]);
+/// Pointer action recorded by `RecordingBackend`
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerAction {
+    MoveAbsolute(i32, i32),
+    MoveRelative(i32, i32),
+    Press,
+    Release,
+    Sleep(Duration),
+}
+
+/// Sink of pointer actions. Left button is the only one used
+pub trait InputBackend {
+    /// Moves the cursor to virtual-desktop pixels. Primary monitor starts at `(0, 0)`,
+    /// so monitors left of or above it have negative coordinates
+    fn move_absolute(&mut self, x: i32, y: i32);
+    fn move_relative(&mut self, dx: i32, dy: i32);
+    fn press(&mut self);
+    fn release(&mut self);
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Sends actions to the system with `winput`
+pub struct WinputBackend;
+
+impl InputBackend for WinputBackend {
+    fn move_absolute(&mut self, x: i32, y: i32) {
+        if let Err(err) = Mouse::set_position(x, y) {
+            eprintln!("Cursor was not moved: {}", err);
+        }
+    }
+
+    fn move_relative(&mut self, dx: i32, dy: i32) {
+        Mouse::move_relative(dx, dy);
+    }
+
+    fn press(&mut self) {
+        winput::send_inputs(&[Input::from_button(Button::Left, Action::Press)]);
+    }
+
+    fn release(&mut self) {
+        winput::send_inputs(&[Input::from_button(Button::Left, Action::Release)]);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        sleep(duration);
+    }
+}
+
+/// Records actions instead of sending them. Sleeps return immediately
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingBackend {
+    pub actions: Vec<PointerAction>,
+}
+
+#[cfg(test)]
+impl RecordingBackend {
+    /// Checks that each move is followed by a delay of `pacing` and each click has its delays,
+    /// and the cursor makes at most `path_steps` intermediate moves between clicks
+    pub fn check_pacing(&self, pacing: &Pacing) -> Result<(), String> {
+        let actions = &self.actions;
+        let check = |i: usize, name: &str, (min, max): (u64, u64)| match actions.get(i) {
+            Some(&PointerAction::Sleep(d)) if (min..=max).contains(&(d.as_millis() as u64)) => Ok(()),
+            other => Err(format!("Action {} is {:?} instead of {} delay {}..={} ms", i + 1, other, name, min, max)),
+        };
+        let (mut i, mut moves) = (0, 0);
+        while i < actions.len() {
+            match actions[i] {
+                PointerAction::MoveAbsolute(_, _) | PointerAction::MoveRelative(_, _) => {
+                    moves += 1;
+                    if moves > pacing.path_steps + 1 {
+                        return Err(format!("Action {} is move {} between clicks", i + 1, moves));
+                    }
+                    match actions.get(i + 2) {
+                        Some(PointerAction::Press) => check(i + 1, "before press", pacing.before_press)?,
+                        _ => check(i + 1, "path step", pacing.step_delay)?,
+                    }
+                    i += 2;
+                }
+                PointerAction::Press => {
+                    check(i + 1, "press", pacing.press)?;
+                    if actions.get(i + 2) != Some(&PointerAction::Release) {
+                        return Err(format!("Action {} is not release", i + 3));
+                    }
+                    check(i + 3, "after release", pacing.after_release)?;
+                    moves = 0;
+                    i += 4;
+                }
+                other => return Err(format!("Action {} is unexpected {:?}", i + 1, other)),
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+impl InputBackend for RecordingBackend {
+    fn move_absolute(&mut self, x: i32, y: i32) {
+        self.actions.push(PointerAction::MoveAbsolute(x, y));
+    }
+
+    fn move_relative(&mut self, dx: i32, dy: i32) {
+        self.actions.push(PointerAction::MoveRelative(dx, dy));
+    }
+
+    fn press(&mut self) {
+        self.actions.push(PointerAction::Press);
+    }
+
+    fn release(&mut self) {
+        self.actions.push(PointerAction::Release);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.actions.push(PointerAction::Sleep(duration));
+    }
+}
+
+/// Clicks solution steps
+pub struct Clicker<'a> {
+    pub input: &'a mut dyn InputBackend,
+    /// Screenshot to cursor mapping. Relative moves are used without it
+    pub mapping: Option<ScreenMapping>,
+    pub pacing: Pacing,
+    /// Remaining steps are skipped once it is set
+    pub abort: Option<&'a AtomicBool>,
+    rng: XorShift,
+}
+
+impl<'a> Clicker<'a> {
+    pub fn new(input: &'a mut dyn InputBackend, mapping: Option<ScreenMapping>, pacing: Pacing, rng: XorShift) -> Self {
+        Self { input, mapping, pacing, abort: None, rng }
+    }
+
+    /// Clicks screenshot `positions` one by one. Returns count of clicked ones, which is less than
+    /// `positions` count if clicking is aborted
+    pub fn click_path(&mut self, positions: &[(u32, u32)]) -> usize {
+        let absolute: Option<Vec<(i32, i32)>> = self.mapping
+            .and_then(|mapping| positions.iter().map(|&(x, y)| mapping.to_cursor(x, y)).collect());
+        match absolute {
+            Some(cursor_positions) => {
+                let mut cur = None;
+                for (i, position) in cursor_positions.into_iter().enumerate() {
+                    if self.aborted() {
+                        return i;
+                    }
+                    let target = self.jitter(position);
+                    self.travel(cur, target, true);
+                    self.press_and_release();
+                    cur = Some(target);
+                }
+                return positions.len();
+            }
+            None => return self.click_path_relative(positions),
+        }
+    }
+
+    /// Cursor is moved to the top left corner first, so positions are reached with relative moves.
+    /// It breaks with mouse acceleration and DPI scaling
+    fn click_path_relative(&mut self, positions: &[(u32, u32)]) -> usize {
+        if self.aborted() {
+            return 0;
+        }
+        self.input.move_relative(-5000, -5000);
+        self.press_and_release();
+        let mut cur = (0, 0);
+        for (i, &(x, y)) in positions.iter().enumerate() {
+            if self.aborted() {
+                return i;
+            }
+            let target = self.jitter((x as i32, y as i32));
+            self.travel(Some(cur), target, false);
+            self.press_and_release();
+            cur = target;
+        }
+        return positions.len();
+    }
+
+    fn aborted(&self) -> bool {
+        self.abort.is_some_and(|abort| abort.load(Acquire))
+    }
+
+    fn jitter(&mut self, (x, y): (i32, i32)) -> (i32, i32) {
+        let radius = self.pacing.jitter;
+        return (x + self.rng.offset(radius), y + self.rng.offset(radius));
+    }
+
+    /// Moves the cursor along interpolated path. It jumps, if the start is unknown
+    fn travel(&mut self, from: Option<(i32, i32)>, to: (i32, i32), absolute: bool) {
+        let path = match from {
+            Some(from) => interpolate(from, to, self.pacing.path_steps),
+            None => vec![to],
+        };
+        let mut prev = from.unwrap_or_default();
+        for (i, (x, y)) in path.into_iter().enumerate() {
+            if i != 0 {
+                let delay = self.rng.delay(self.pacing.step_delay);
+                self.input.sleep(delay);
+            }
+            if absolute {
+                self.input.move_absolute(x, y);
+            } else {
+                self.input.move_relative(x - prev.0, y - prev.1);
+            }
+            prev = (x, y);
+        }
+    }
+
+    fn press_and_release(&mut self) {
+        let delay = self.rng.delay(self.pacing.before_press);
+        self.input.sleep(delay);
+        self.input.press();
+        let delay = self.rng.delay(self.pacing.press);
+        self.input.sleep(delay);
+        self.input.release();
+        let delay = self.rng.delay(self.pacing.after_release);
+        self.input.sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::{Acquire, Release};
+    use std::time::Duration;
+
+    use crate::input::{Clicker, InputBackend, PointerAction, RecordingBackend};
+    use crate::monitor::{Desktop, ScreenMapping};
+    use crate::pacing::{Pacing, XorShift};
+
+    #[test]
+    fn test_click_path() {
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, None, Pacing::default(), XorShift::new(1)).click_path(&[(100, 50), (100, 90), (40, 90)]);
+
+        let moves: Vec<PointerAction> = input.actions.iter()
+            .filter(|a| matches!(a, PointerAction::MoveRelative(_, _)))
+            .copied()
+            .collect();
+        assert_eq!(vec![
+            PointerAction::MoveRelative(-5000, -5000),
+            PointerAction::MoveRelative(100, 50),
+            PointerAction::MoveRelative(0, 40),
+            PointerAction::MoveRelative(-60, 0),
+        ], moves);
+
+        let click = [
+            PointerAction::Sleep(Duration::from_millis(300)),
+            PointerAction::Press,
+            PointerAction::Sleep(Duration::from_millis(30)),
+            PointerAction::Release,
+            PointerAction::Sleep(Duration::from_millis(200)),
+        ];
+        assert_eq!(4 * 6, input.actions.len());
+        for chunk in input.actions.chunks(6) {
+            assert_eq!(&click, &chunk[1..]);
+        }
+    }
+
+    #[test]
+    fn test_click_path_absolute() {
+        let desktop = Desktop { virtual_screen: (-1280, 0, 3200, 1080), primary: (1920, 1080), dpi: 96 };
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, ScreenMapping::new(3200, 1080, &desktop), Pacing::default(), XorShift::new(1)).click_path(&[(1380, 50), (1380, 90)]);
+
+        let moves: Vec<PointerAction> = input.actions.iter()
+            .filter(|a| !matches!(a, PointerAction::Sleep(_)))
+            .copied()
+            .collect();
+        assert_eq!(vec![
+            PointerAction::MoveAbsolute(100, 50),
+            PointerAction::Press,
+            PointerAction::Release,
+            PointerAction::MoveAbsolute(100, 90),
+            PointerAction::Press,
+            PointerAction::Release,
+        ], moves);
+    }
+
+    #[test]
+    fn test_click_path_absolute_left_monitor() {
+        // screenshot of the whole desktop, the left monitor is above the primary one
+        let desktop = Desktop { virtual_screen: (-1280, -56, 3200, 1136), primary: (1920, 1080), dpi: 96 };
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, ScreenMapping::new(3200, 1136, &desktop), Pacing::default(), XorShift::new(1)).click_path(&[(200, 20), (1380, 156)]);
+
+        let moves: Vec<PointerAction> = input.actions.iter()
+            .filter(|a| matches!(a, PointerAction::MoveAbsolute(_, _)))
+            .copied()
+            .collect();
+        assert_eq!(vec![PointerAction::MoveAbsolute(-1080, -36), PointerAction::MoveAbsolute(100, 100)], moves);
+    }
+
+    #[test]
+    fn test_click_path_falls_back_to_relative() {
+        // the last position is outside the desktop
+        let desktop = Desktop { virtual_screen: (0, 0, 1920, 1080), primary: (1920, 1080), dpi: 96 };
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, ScreenMapping::new(1920, 1080, &desktop), Pacing::default(), XorShift::new(1)).click_path(&[(100, 50), (100, 2000)]);
+        assert_eq!(Some(&PointerAction::MoveRelative(-5000, -5000)), input.actions.first());
+        assert!(!input.actions.iter().any(|a| matches!(a, PointerAction::MoveAbsolute(_, _))));
+    }
+
+    #[test]
+    fn test_click_path_paced() {
+        let pacing = Pacing::human();
+        let positions = [(100, 50), (100, 250), (400, 250)];
+
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, None, pacing, XorShift::new(7)).click_path(&positions);
+        assert_eq!(Ok(()), input.check_pacing(&pacing));
+        // reset move, then whole paths
+        let moves = input.actions.iter().filter(|a| matches!(a, PointerAction::MoveRelative(_, _))).count();
+        assert_eq!(1 + positions.len() * (pacing.path_steps as usize + 1), moves);
+
+        // cursor is near each target at press
+        let mut cur = (0, 0);
+        let mut clicked = Vec::new();
+        for action in input.actions.iter().skip(6) {
+            match *action {
+                PointerAction::MoveRelative(dx, dy) => cur = (cur.0 + dx, cur.1 + dy),
+                PointerAction::Press => clicked.push(cur),
+                _ => {}
+            }
+        }
+        assert_eq!(positions.len(), clicked.len());
+        for (&(x, y), &(cx, cy)) in positions.iter().zip(clicked.iter()) {
+            assert!((cx - x as i32).unsigned_abs() <= pacing.jitter && (cy - y as i32).unsigned_abs() <= pacing.jitter);
+        }
+
+        let desktop = Desktop { virtual_screen: (0, 0, 1920, 1080), primary: (1920, 1080), dpi: 96 };
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, ScreenMapping::new(1920, 1080, &desktop), pacing, XorShift::new(7)).click_path(&positions);
+        assert_eq!(Ok(()), input.check_pacing(&pacing));
+        // the first cell is reached with a jump
+        let moves = input.actions.iter().filter(|a| matches!(a, PointerAction::MoveAbsolute(_, _))).count();
+        assert_eq!(1 + (positions.len() - 1) * (pacing.path_steps as usize + 1), moves);
+    }
+
+    #[test]
+    fn test_check_pacing() {
+        let mut input = RecordingBackend::default();
+        Clicker::new(&mut input, None, Pacing::default(), XorShift::new(1)).click_path(&[(100, 50)]);
+        assert_eq!(Ok(()), input.check_pacing(&Pacing::default()));
+
+        let fast = Pacing { before_press: (50, 100), ..Pacing::default() };
+        assert!(input.check_pacing(&fast).is_err());
+        input.actions.push(PointerAction::Press);
+        assert!(input.check_pacing(&Pacing::default()).is_err());
+    }
+
+    /// Sets `abort` on `n`-th release, like `Escape` pressed during a click
+    struct AbortingBackend<'a> {
+        recording: RecordingBackend,
+        abort: &'a AtomicBool,
+        n: usize,
+    }
+
+    impl InputBackend for AbortingBackend<'_> {
+        fn move_absolute(&mut self, x: i32, y: i32) {
+            self.recording.move_absolute(x, y);
+        }
+
+        fn move_relative(&mut self, dx: i32, dy: i32) {
+            self.recording.move_relative(dx, dy);
+        }
+
+        fn press(&mut self) {
+            self.recording.press();
+        }
+
+        fn release(&mut self) {
+            self.recording.release();
+            let releases = self.recording.actions.iter().filter(|&&a| a == PointerAction::Release).count();
+            if releases == self.n {
+                self.abort.store(true, Release);
+            }
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.recording.sleep(duration);
+        }
+    }
+
+    #[test]
+    fn test_click_path_abort() {
+        let positions = [(100, 50), (100, 250), (400, 250), (400, 50)];
+        let desktop = Desktop { virtual_screen: (0, 0, 1920, 1080), primary: (1920, 1080), dpi: 96 };
+
+        let abort = AtomicBool::new(false);
+        let mut input = AbortingBackend { recording: RecordingBackend::default(), abort: &abort, n: 2 };
+        let mut clicker = Clicker::new(&mut input, ScreenMapping::new(1920, 1080, &desktop), Pacing::default(), XorShift::new(1));
+        clicker.abort = Some(&abort);
+        assert_eq!(2, clicker.click_path(&positions));
+        assert_eq!(2, input.recording.actions.iter().filter(|&&a| a == PointerAction::Press).count());
+        assert!(abort.load(Acquire));
+
+        // the first click of relative path resets cursor
+        let abort = AtomicBool::new(false);
+        let mut input = AbortingBackend { recording: RecordingBackend::default(), abort: &abort, n: 2 };
+        let mut clicker = Clicker::new(&mut input, None, Pacing::default(), XorShift::new(1));
+        clicker.abort = Some(&abort);
+        assert_eq!(1, clicker.click_path(&positions));
 
-    sleep(Duration::from_millis(30));
+        // aborted before start
+        let mut input = RecordingBackend::default();
+        let mut clicker = Clicker::new(&mut input, None, Pacing::default(), XorShift::new(1));
+        clicker.abort = Some(&abort);
+        assert_eq!(0, clicker.click_path(&positions));
+        assert!(input.actions.is_empty());
 
-    let input = Input::from_button(Button::Left, Action::Release);
-    winput::send_inputs(&[input]);
-    sleep(Duration::from_millis(200));
+        let mut input = RecordingBackend::default();
+        assert_eq!(positions.len(), Clicker::new(&mut input, None, Pacing::default(), XorShift::new(1)).click_path(&positions));
+    }
 }
@@ -1,47 +1,112 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release};
 use std::thread;
 use std::time::Duration;
 
-use bmp::Image;
 use winapi::shared::minwindef::{LPARAM, LRESULT, WPARAM};
 use winapi::um::winuser;
 
-use crate::img::{GrayImage, load_img_from_clipboard, load_img_from_file};
-use crate::input::click;
-use crate::ocr::{ocr_matrix, ocr_conditions, MatrixTemplates};
-use crate::recognize::{CONDITION_COLOR, MATRIX_COLOR};
+use crate::debug::{dump_mask, Overlay, start_mask_dump, stop_mask_dump};
+use crate::img::{GrayImage, load_img_from_clipboard, load_img_from_file, RgbImage};
+use crate::input::{Clicker, WinputBackend};
+use crate::monitor::{query_desktop, ScreenMapping, set_dpi_aware};
+use crate::pacing::{Pacing, XorShift};
+use crate::ocr::{CellState, codes, Daemon, DaemonStatus, MatchMethod, MatrixState, MatrixTemplates, OcrOptions};
+use crate::recognize::StageFilters;
+use crate::screen::{classify_screen, Screen};
+use crate::text::{ocr_daemon_names, ocr_timer, TextGlyphs};
 
+mod color;
+mod debug;
+mod dib;
 mod img;
+mod inflate;
+mod morph;
 mod ocr;
+mod pacing;
+mod png;
 mod recognize;
+mod screen;
 mod solver;
+mod text;
+mod train;
 mod util;
 mod input;
+mod monitor;
 #[cfg(test)]
 mod test_cases;
+#[cfg(test)]
+mod test_layouts;
 
 static LOCK: AtomicBool = AtomicBool::new(false);
+// saves each captured screenshot with its masks into working directory
+static DUMP_MASKS: AtomicBool = AtomicBool::new(false);
+// color filters selected by `--filter`
+static FILTERS: OnceLock<StageFilters> = OnceLock::new();
+// template matcher and rejection threshold selected by `--matcher` and `--max-error`
+static OCR_OPTIONS: OnceLock<OcrOptions> = OnceLock::new();
+// template pack selected by `--templates` or embedded one
+static TEMPLATES: OnceLock<MatrixTemplates> = OnceLock::new();
+// daemon title font selected by `--font` or bundled one
+static TITLE_FONT: OnceLock<TextGlyphs> = OnceLock::new();
+// breach timer font selected by `--timer-font` or bundled digits
+static TIMER_FONT: OnceLock<TextGlyphs> = OnceLock::new();
+// click timings and cursor paths selected by `--pacing`
+static PACING: OnceLock<Pacing> = OnceLock::new();
+// set by `Escape` while the bot is running, clicking stops before the next step
+static ABORT: AtomicBool = AtomicBool::new(false);
+// `Escape` key down aborted clicking and was hidden from the game, so its key up is hidden too
+static ESCAPE_SWALLOWED: AtomicBool = AtomicBool::new(false);
 
 #[allow(dead_code)]
 unsafe extern "system" fn keyboard_hook(code: i32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
-    if code == winuser::HC_ACTION && w_param == winuser::WM_KEYUP as _ {
+    if code == winuser::HC_ACTION {
         let info: winuser::PKBDLLHOOKSTRUCT = std::mem::transmute(l_param);
-        if (*info).vkCode == winuser::VK_SNAPSHOT as _ {
+        if (*info).vkCode == winuser::VK_ESCAPE as _ {
+            // the game would close the breach
+            if w_param == winuser::WM_KEYDOWN as _ && LOCK.load(Acquire) {
+                ABORT.store(true, Release);
+                ESCAPE_SWALLOWED.store(true, Release);
+                return 1;
+            }
+            if w_param == winuser::WM_KEYUP as _ && ESCAPE_SWALLOWED.swap(false, AcqRel) {
+                return 1;
+            }
+        }
+        if w_param == winuser::WM_KEYUP as _ && (*info).vkCode == winuser::VK_SNAPSHOT as _ {
             if LOCK.compare_exchange(false, true, Acquire, Acquire) == Ok(false) {
+                ABORT.store(false, Release);
                 thread::spawn(|| {
-                    let templates = MatrixTemplates::load_templates();
+                    let templates = TEMPLATES.get_or_init(MatrixTemplates::load_templates);
+                    let font = TITLE_FONT.get_or_init(TextGlyphs::load_font);
+                    let timer_font = TIMER_FONT.get_or_init(TextGlyphs::load_timer_font);
+                    let filters = FILTERS.get_or_init(StageFilters::default);
+                    let ocr_options = OCR_OPTIONS.get_or_init(OcrOptions::default);
                     // wait for clipboard buffer initialization
                     thread::sleep(Duration::from_millis(600));
                     match load_img_from_clipboard() {
-                        None => {
-                            eprintln!("Clipboard has no image data");
+                        Err(err) => {
+                            eprintln!("{}", err);
                         }
-                        Some(img) => {
-                            let result = execute(img, &templates, false);
+                        Ok(img) => {
+                            if DUMP_MASKS.load(Acquire) {
+                                save_capture(&img);
+                            }
+                            let mapping = ScreenMapping::new(img.width(), img.height(), &query_desktop());
+                            if mapping.is_none() {
+                                eprintln!("Warning: screenshot is not the whole desktop or primary monitor. Cursor is moved relatively");
+                            }
+                            let mut input = WinputBackend;
+                            let pacing = *PACING.get_or_init(Pacing::default);
+                            let mut clicker = Clicker::new(&mut input, mapping, pacing, XorShift::from_time());
+                            clicker.abort = Some(&ABORT);
+                            let result = execute(img, templates, font, timer_font, filters, ocr_options, Some(&mut clicker), None);
                             if result.is_err() {
                                 eprintln!("{}", result.unwrap_err());
                             }
+                            stop_mask_dump();
                         }
                     };
                     LOCK.store(false, Release);
@@ -52,46 +117,125 @@ unsafe extern "system" fn keyboard_hook(code: i32, w_param: WPARAM, l_param: LPA
     winuser::CallNextHookEx(std::ptr::null_mut(), code, w_param, l_param)
 }
 
-fn execute(img: Image, templates: &MatrixTemplates, solutions_only: bool) -> Result<(), String> {
-    let matrix_area = recognize::find_matrix_area(&img).ok_or_else(|| "Matrix was not found".to_owned())?;
-    let matrix_img = GrayImage::filter(&img, &MATRIX_COLOR, 50, matrix_area.0, matrix_area.1, matrix_area.2, matrix_area.3);
-    let matrix = match ocr_matrix(&matrix_img, templates) {
+/// Saves clipboard screenshot into working directory and starts mask dump alongside it
+fn save_capture(img: &RgbImage) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = format!("capture-{}.bmp", secs);
+    match img.save(&path) {
+        Ok(_) => {
+            println!("Screenshot was saved to {}", &path);
+            start_mask_dump(&path);
+        }
+        Err(e) => eprintln!("Screenshot was not saved: {}", e),
+    }
+}
+
+/// `clicker` - clicks the best solution. Solutions are only printed without it.
+/// `overlay_path` - optional bmp file path. Everything the bot has seen is drawn over the screenshot
+/// and saved there, even if recognition fails
+fn execute(img: RgbImage, templates: &MatrixTemplates, font: &TextGlyphs, timer_font: &TextGlyphs, filters: &StageFilters, ocr_options: &OcrOptions, clicker: Option<&mut Clicker>, overlay_path: Option<&Path>) -> Result<(), String> {
+    let mut overlay = overlay_path.map(|_| Overlay::default());
+    let result = recognize_and_solve(&img, templates, font, timer_font, filters, ocr_options, clicker, overlay.as_mut());
+
+    if let (Some(overlay), Some(path)) = (overlay, overlay_path) {
+        overlay.save(&img, path)?;
+        println!("Overlay was saved to {}", path.display());
+    }
+    return result;
+}
+
+fn recognize_and_solve(img: &RgbImage, templates: &MatrixTemplates, font: &TextGlyphs, timer_font: &TextGlyphs, filters: &StageFilters, ocr_options: &OcrOptions, clicker: Option<&mut Clicker>, mut overlay: Option<&mut Overlay>) -> Result<(), String> {
+    let screen = match classify_screen(img, filters) {
+        Screen::NotBreach => return Err("Not a breach protocol screen".to_owned()),
+        Screen::BreachFinished => return Err("Breach is finished".to_owned()),
+        screen => screen,
+    };
+    let layout = recognize::find_layout(&img, filters)?;
+    let matrix_area = layout.matrix;
+    if let Some(overlay) = overlay.as_deref_mut() {
+        overlay.add_area(&matrix_area);
+    }
+    let matrix = match recognize::read_matrix(img, &matrix_area, filters, templates, ocr_options) {
         Ok(r) => r,
         Err(err) => Err(format!("Matrix was not recognized: {}", err))?,
     };
-    drop(matrix_img);
+    if let Some(overlay) = overlay.as_deref_mut() {
+        overlay.add_matrix(&matrix_area, &matrix);
+    }
 
-    println!("Matrix:");
-    for line in matrix.4.iter() {
-        let hex = line.iter()
-            .map(|v| format!("{:#04x} ", v))
-            .collect::<String>();
-        println!("{}", hex);
+    println!("Matrix (confidence):");
+    print_matrix(&matrix);
+    println!();
+    for &(x, y) in matrix.uncertain.iter() {
+        eprintln!("Warning: matrix item at column {} row {} is uncertain", x + 1, y + 1);
     }
+    let matrix_codes = matrix.cell_codes();
+
+    let time = read_timer(img, &matrix_area, filters, timer_font);
     println!();
 
-    let condition_area = recognize::find_condition_area(&img, &matrix_area).ok_or_else(|| "Conditions were not found".to_owned())?;
-    let condition_img = GrayImage::filter(&img, &CONDITION_COLOR, 50, condition_area.0, condition_area.1, condition_area.2, condition_area.3);
-    let conditions = match ocr_conditions(&condition_img, templates) {
+    let condition_area = layout.conditions;
+    if let Some(overlay) = overlay.as_deref_mut() {
+        overlay.add_area(&condition_area);
+    }
+    let daemons = match recognize::read_daemons(img, &condition_area, filters, templates, ocr_options) {
         Ok(r) => r,
         Err(err) => Err(format!("Conditions were not recognized: {}", err))?,
     };
-    drop(condition_img);
-
-    println!("Conditions:");
-    for line in conditions.iter() {
-        let hex = line.iter()
-            .map(|v| format!("{:#04x} ", v))
-            .collect::<String>();
-        println!("{}", hex);
+    for (i, daemon) in daemons.iter().enumerate() {
+        for &x in daemon.uncertain.iter() {
+            eprintln!("Warning: daemon {} item at column {} is uncertain", i + 1, x + 1);
+        }
     }
+
+    let names = match layout.descriptions {
+        Some(area) => {
+            // description top is the same as condition top
+            let description_img = GrayImage::filter_by(&img, &filters.condition_border, area.0, area.1, area.2, area.3);
+            dump_mask("daemon_names", &description_img, &area, &filters.condition_border);
+            ocr_daemon_names(&description_img, &daemons, font)
+        }
+        None => vec![String::new(); daemons.len()],
+    };
+
+    println!("Conditions (confidence):");
+    print_daemons(&daemons, &names);
     println!();
+    // installed and failed daemons can't be changed
+    let conditions: Vec<Vec<u8>> = daemons.iter()
+        .filter(|d| d.status == DaemonStatus::Pending)
+        .map(|d| d.codes.iter().map(|c| c.code).collect())
+        .collect();
 
-    let steps = recognize::find_buffer_size(&img, &condition_area).ok_or_else(|| "Buffer size was not recognized".to_owned())?;
+    let buffer_box = layout.buffer.ok_or_else(|| "Buffer size was not recognized".to_owned())?;
+    if let Some(overlay) = overlay.as_deref_mut() {
+        overlay.add_area(&buffer_box.area);
+    }
+    let steps = buffer_box.size;
     println!("Steps: {}", steps);
+    let buffer = match recognize::read_buffer(img, &buffer_box, filters, templates, ocr_options) {
+        Ok(r) => r,
+        Err(err) => Err(format!("Buffer was not recognized: {}", err))?,
+    };
+    let buffer_hex = buffer.iter()
+        .map(|code| code.map(|c| format!("{:#04x} ", c)).unwrap_or_else(|| "[  ] ".to_owned()))
+        .collect::<String>();
+    println!("Buffer: {}", buffer_hex);
     println!();
+    let committed: Vec<u8> = buffer.iter().map_while(|&code| code).collect();
 
-    let solutions = solver::solve(&matrix.4, &conditions, steps);
+    let screen = screen.with_progress(matrix.used_count(), committed.len());
+    let solutions = match (screen, matrix.code_cells(), matrix.active_line) {
+        (Screen::BreachIdle, Some(cells), _) => solver::solve(&codes(&cells), &conditions, steps),
+        (_, _, Some(line)) => {
+            println!("Breach is in progress: {} cells are used, {} steps are left", matrix.used_count(), steps.saturating_sub(committed.len()));
+            solver::solve_from(&matrix_codes, line, &committed, &conditions, steps.saturating_sub(committed.len()))
+        }
+        (_, _, None) => return Err("Breach is in progress, but active line was not recognized".to_owned()),
+    };
     println!("Found {} solutions", solutions.len());
     let best = solver::filter_best(&solutions);
     println!("{} best solutions:", best.len());
@@ -100,40 +244,180 @@ fn execute(img: Image, templates: &MatrixTemplates, solutions_only: bool) -> Res
             .map(|&b| if b { "✔ " } else { "✖ " })
             .collect::<String>();
         let steps = s.steps.iter()
-            .map(|step| matrix.4[step.y as usize][step.x as usize])
+            .filter_map(|step| matrix_codes[step.y as usize][step.x as usize])
             .map(|item| format!("{:#04x} ", item))
             .collect::<String>();
         println!("Solution #{}, conditions: {}, steps: {}", i + 1, conditions, steps);
     }
     println!();
 
-    if !solutions_only && !best.is_empty() {
-        let left = matrix_area.0 + matrix.0;
-        let top = matrix_area.1 + matrix.1;
-        let item_width = (matrix.2 - matrix.0) / (matrix.4.len() - 1) as u32;
-        let item_height = (matrix.3 - matrix.1) / (matrix.4.len() - 1) as u32;
+    // the timer limits steps, which can be clicked
+    let step_duration = clicker.as_ref().map(|c| c.pacing).unwrap_or_default().max_step_duration();
+    let solution = match time {
+        Some(seconds) if !step_duration.is_zero() => {
+            let step_limit = (seconds / step_duration.as_secs_f64()) as usize;
+            println!("Time is enough for {} steps", step_limit);
+            solver::best_within(&best, step_limit)
+        }
+        _ => best.last(),
+    };
 
-        let mut cur = (0, 0);
-        click(-5000, -5000);
-        for s in best.last().unwrap().steps.iter() {
-            let x = s.x as u32 * item_width + left + 15;
-            let y = s.y as u32 * item_height + top + 10;
+    let left = matrix_area.0 + matrix.left;
+    let top = matrix_area.1 + matrix.top;
+    let item_width = (matrix.right - matrix.left) / (matrix_codes.len() - 1) as u32;
+    let item_height = (matrix.bottom - matrix.top) / (matrix_codes.len() - 1) as u32;
+    let step_position = |s: &solver::Step| (s.x as u32 * item_width + left + 15, s.y as u32 * item_height + top + 10);
 
-            click(x as i32 - cur.0, y as i32 - cur.1);
-            cur = (x as i32, y as i32);
+    if let (Some(overlay), Some(solution)) = (overlay.as_deref_mut(), solution) {
+        overlay.set_path(solution.steps.iter().map(step_position).collect());
+    }
+
+    if let (Some(clicker), Some(solution)) = (clicker, solution) {
+        let positions: Vec<(u32, u32)> = solution.steps.iter().map(step_position).collect();
+        let applied = clicker.click_path(&positions);
+        if applied < positions.len() {
+            return Err(format!("Clicking was aborted after {} of {} steps", applied, positions.len()));
         }
+        println!("{} steps were clicked", applied);
     }
     Ok(())
 }
 
+/// Remaining breach time in seconds. `None` if the timer is not recognized
+fn read_timer(img: &RgbImage, matrix_area: &(u32, u32, u32, u32), filters: &StageFilters, font: &TextGlyphs) -> Option<f64> {
+    let area = match recognize::find_timer_area(img, matrix_area, filters) {
+        Some(area) => area,
+        None => {
+            eprintln!("Warning: timer was not found");
+            return None;
+        }
+    };
+    let timer_img = GrayImage::filter_by(img, &filters.matrix, area.0, area.1, area.2, area.3);
+    dump_mask("timer", &timer_img, &area, &filters.matrix);
+    return match ocr_timer(&timer_img, font) {
+        Ok(seconds) => {
+            println!("Time: {:.2} s", seconds);
+            Some(seconds)
+        }
+        Err(err) => {
+            eprintln!("Warning: timer was not recognized: {}", err);
+            None
+        }
+    };
+}
+
+/// Used cells are printed as `[ ]`, highlighted ones are marked with `*`
+fn print_matrix(matrix: &MatrixState) {
+    for line in matrix.cells.iter() {
+        let hex = line.iter()
+            .map(|c| {
+                let mark = if c.highlighted { '*' } else { ' ' };
+                match c.state {
+                    CellState::Code(cell) => format!("{:#04x} ({:.2}){}", cell.code, cell.confidence(), mark),
+                    CellState::Used => format!("[ ]         {}", mark),
+                }
+            })
+            .collect::<String>();
+        println!("{}", hex);
+    }
+}
+
+/// Shifted codes are padded with blank columns
+fn print_daemons(daemons: &[Daemon], names: &[String]) {
+    let name_width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    for (daemon, name) in daemons.iter().zip(names) {
+        let hex = match daemon.status {
+            DaemonStatus::Pending => daemon.codes.iter()
+                .map(|c| format!("{:#04x} ({:.2}) ", c.code, c.confidence()))
+                .collect::<String>(),
+            DaemonStatus::Installed => "INSTALLED".to_owned(),
+            DaemonStatus::Failed => "FAILED".to_owned(),
+        };
+        println!("{:<w$} {}{}", name, " ".repeat(12 * daemon.offset), hex, w = name_width);
+    }
+}
+
 fn main() {
-    if std::env::args().len() > 1 {
-        let bmp_path = std::env::args().last().unwrap();
-        println!("Reading {} bmp file...", &bmp_path);
-        let img = load_img_from_file(bmp_path);
-        execute(img, &MatrixTemplates::load_templates(), true).expect("Error");
+    let mut overlay_path = None;
+    let mut dump_masks = false;
+    let mut filters = StageFilters::default();
+    let mut method = MatchMethod::default();
+    let mut max_error = None;
+    let mut pacing = Pacing::default();
+    let mut templates_path = None;
+    let mut font_path = None;
+    let mut timer_font_path = None;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--overlay" => overlay_path = Some(args.next().expect("--overlay requires output bmp path")),
+            "--dump-masks" => dump_masks = true,
+            "--filter" => {
+                let spec = args.next().expect("--filter requires rgb, hsv, lab or per stage filters, e.g. hsv,condition=lab");
+                filters = StageFilters::parse(&spec).unwrap_or_else(|e| panic!("{}", e));
+            }
+            "--templates" => templates_path = Some(args.next().expect("--templates requires pack directory or manifest path")),
+            "--font" => font_path = Some(args.next().expect("--font requires font pack directory")),
+            "--timer-font" => timer_font_path = Some(args.next().expect("--timer-font requires font pack directory")),
+            "--matcher" => {
+                let name = args.next().expect("--matcher requires mismatch or ncc");
+                method = MatchMethod::by_name(&name).unwrap_or_else(|| panic!("Unknown matcher {}", name));
+            }
+            "--max-error" => {
+                let value = args.next().expect("--max-error requires a number");
+                max_error = Some(value.parse::<f64>().unwrap_or_else(|_| panic!("Bad max error {}", value)));
+            }
+            "--pacing" => {
+                let spec = args.next().expect("--pacing requires profile, e.g. human or default,press=40-80");
+                pacing = Pacing::parse(&spec).unwrap_or_else(|e| panic!("{}", e));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.first().map(String::as_str) == Some("train") {
+        let output = positional.get(1).expect("train requires output pack directory");
+        let screenshots: Vec<PathBuf> = positional[2..].iter().map(PathBuf::from).collect();
+        if screenshots.is_empty() {
+            panic!("train requires at least 1 labeled screenshot");
+        }
+        let has_font = train::train_files(Path::new(output), &screenshots, &filters).expect("Error");
+        println!("Template pack was saved to {}", output);
+        if has_font {
+            println!("Font pack was saved to {}", Path::new(output).join("font").display());
+        }
         return;
     }
+    let image_path = positional.pop();
+
+    let ocr_options = OcrOptions { method, max_error: max_error.unwrap_or(method.default_max_error()) };
+    let templates = MatrixTemplates::load_or_embedded(templates_path.as_deref().map(Path::new)).expect("Error");
+    let font = TextGlyphs::load_or_bundled(font_path.as_deref().map(Path::new)).expect("Error");
+    let timer_font = TextGlyphs::load_timer_or_bundled(timer_font_path.as_deref().map(Path::new)).expect("Error");
+    if let Some(image_path) = image_path {
+        println!("Reading {} image file...", &image_path);
+        if dump_masks {
+            start_mask_dump(&image_path);
+        }
+        let img = load_img_from_file(&image_path).expect("Error");
+        execute(img, &templates, &font, &timer_font, &filters, &ocr_options, None, overlay_path.as_deref().map(Path::new)).expect("Error");
+        return;
+    }
+    DUMP_MASKS.store(dump_masks, Release);
+    set_dpi_aware();
+    FILTERS.set(filters).unwrap();
+    OCR_OPTIONS.set(ocr_options).unwrap();
+    PACING.set(pacing).unwrap();
+    if TEMPLATES.set(templates).is_err() {
+        panic!("Templates are already loaded");
+    }
+    if TITLE_FONT.set(font).is_err() {
+        panic!("Font is already loaded");
+    }
+    if TIMER_FONT.set(timer_font).is_err() {
+        panic!("Timer font is already loaded");
+    }
 
 
     let hook = unsafe {
@@ -143,7 +427,7 @@ fn main() {
         panic!("SetWindowsHookExA returns null");
     }
 
-    println!("Press PrintScreen keyboard button to trigger the bot...");
+    println!("Press PrintScreen keyboard button to trigger the bot, Escape to stop clicking...");
     unsafe {
         let mut msg: winuser::MSG = std::mem::zeroed();
         while 0 == winuser::GetMessageA(&mut msg, std::ptr::null_mut(), 0, 0) {
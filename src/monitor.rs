@@ -0,0 +1,162 @@
+use std::ptr;
+use winapi::um::{wingdi, winuser};
+
+// cursor coordinates are screenshot pixels at this DPI
+const BASE_DPI: u32 = 96;
+// screenshot size may differ from scaled desktop size by rounding
+const SIZE_TOLERANCE: u32 = 2;
+
+/// Desktop as the process sees it, in cursor coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desktop {
+    /// `(left, top, width, height)` of all monitors. Left and top are negative,
+    /// if a monitor is to the left of or above the primary one
+    pub virtual_screen: (i32, i32, u32, u32),
+    /// Primary monitor size. Its top left corner is `(0, 0)`
+    pub primary: (u32, u32),
+    /// Screenshot pixels per 96 cursor units. It is 96 for DPI aware process
+    pub dpi: u32,
+}
+
+impl Desktop {
+    fn to_pixels(self, size: u32) -> u32 {
+        (size as u64 * self.dpi as u64 / BASE_DPI as u64) as u32
+    }
+
+    fn matches(&self, (width, height): (u32, u32), img_width: u32, img_height: u32) -> bool {
+        self.to_pixels(width).abs_diff(img_width) <= SIZE_TOLERANCE && self.to_pixels(height).abs_diff(img_height) <= SIZE_TOLERANCE
+    }
+}
+
+/// Maps screenshot pixels to absolute cursor positions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenMapping {
+    desktop: Desktop,
+    /// Cursor position of screenshot top left corner
+    origin: (i32, i32),
+}
+
+impl ScreenMapping {
+    /// Screenshot must be the whole desktop (`PrintScreen`) or the primary monitor.
+    /// Returns `None` for anything else, e.g. a window captured with `Alt + PrintScreen`
+    pub fn new(img_width: u32, img_height: u32, desktop: &Desktop) -> Option<Self> {
+        if desktop.dpi == 0 {
+            return None;
+        }
+        let (left, top, width, height) = desktop.virtual_screen;
+        let origin = if desktop.matches((width, height), img_width, img_height) {
+            (left, top)
+        } else if desktop.matches(desktop.primary, img_width, img_height) {
+            (0, 0)
+        } else {
+            return None;
+        };
+        return Some(Self { desktop: *desktop, origin });
+    }
+
+    /// Cursor position of screenshot pixel. `None` if it is outside the desktop
+    pub fn to_cursor(self, x: u32, y: u32) -> Option<(i32, i32)> {
+        let scale = |v: u32| ((v as u64 * BASE_DPI as u64 + self.desktop.dpi as u64 / 2) / self.desktop.dpi as u64) as i32;
+        let (cursor_x, cursor_y) = (self.origin.0 + scale(x), self.origin.1 + scale(y));
+        let (left, top, width, height) = self.desktop.virtual_screen;
+        if cursor_x < left || cursor_y < top || cursor_x >= left + width as i32 || cursor_y >= top + height as i32 {
+            return None;
+        }
+        return Some((cursor_x, cursor_y));
+    }
+}
+
+/// Makes cursor coordinates match screenshot pixels, so `query_desktop` reports 96 DPI.
+/// Returns false if Windows keeps scaling them
+pub fn set_dpi_aware() -> bool {
+    unsafe { winuser::SetProcessDPIAware() != 0 }
+}
+
+pub fn query_desktop() -> Desktop {
+    let metric = |index: i32| unsafe { winuser::GetSystemMetrics(index) };
+    Desktop {
+        virtual_screen: (
+            metric(winuser::SM_XVIRTUALSCREEN),
+            metric(winuser::SM_YVIRTUALSCREEN),
+            metric(winuser::SM_CXVIRTUALSCREEN).max(0) as u32,
+            metric(winuser::SM_CYVIRTUALSCREEN).max(0) as u32,
+        ),
+        primary: (metric(winuser::SM_CXSCREEN).max(0) as u32, metric(winuser::SM_CYSCREEN).max(0) as u32),
+        dpi: query_dpi(),
+    }
+}
+
+/// Windows reports scaled desktop size to DPI unaware process, but the real one to device context
+fn query_dpi() -> u32 {
+    unsafe {
+        let dc = winuser::GetDC(ptr::null_mut());
+        if dc.is_null() {
+            return 0;
+        }
+        let logical = wingdi::GetDeviceCaps(dc, wingdi::HORZRES);
+        let physical = wingdi::GetDeviceCaps(dc, wingdi::DESKTOPHORZRES);
+        winuser::ReleaseDC(ptr::null_mut(), dc);
+        return scale_dpi(physical, logical);
+    }
+}
+
+/// DPI of `physical` pixels per `logical` cursor units. 0 if either is unknown
+fn scale_dpi(physical: i32, logical: i32) -> u32 {
+    if physical <= 0 || logical <= 0 {
+        return 0;
+    }
+    return (physical as u64 * BASE_DPI as u64 / logical as u64) as u32;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::monitor::{Desktop, scale_dpi, ScreenMapping};
+
+    #[test]
+    fn test_single_monitor() {
+        let desktop = Desktop { virtual_screen: (0, 0, 1920, 1080), primary: (1920, 1080), dpi: 96 };
+        let mapping = ScreenMapping::new(1920, 1080, &desktop).unwrap();
+        assert_eq!(Some((0, 0)), mapping.to_cursor(0, 0));
+        assert_eq!(Some((1919, 1079)), mapping.to_cursor(1919, 1079));
+        assert_eq!(None, mapping.to_cursor(1920, 10));
+
+        // window screenshot
+        assert_eq!(None, ScreenMapping::new(1280, 720, &desktop));
+    }
+
+    #[test]
+    fn test_secondary_monitor_on_the_left() {
+        // 1280x1024 monitor to the left of 1920x1080 primary one, aligned by bottom
+        let desktop = Desktop { virtual_screen: (-1280, -56, 3200, 1136), primary: (1920, 1080), dpi: 96 };
+        let mapping = ScreenMapping::new(3200, 1136, &desktop).unwrap();
+        assert_eq!(Some((-1280, -56)), mapping.to_cursor(0, 0));
+        assert_eq!(Some((0, 0)), mapping.to_cursor(1280, 56));
+        assert_eq!(Some((500, 300)), mapping.to_cursor(1780, 356));
+
+        // fullscreen game on primary monitor
+        let mapping = ScreenMapping::new(1920, 1080, &desktop).unwrap();
+        assert_eq!(Some((500, 300)), mapping.to_cursor(500, 300));
+    }
+
+    #[test]
+    fn test_dpi_scaling() {
+        // 3840x2160 at 150% is 2560x1440 for DPI unaware process
+        let desktop = Desktop { virtual_screen: (0, 0, 2560, 1440), primary: (2560, 1440), dpi: 144 };
+        let mapping = ScreenMapping::new(3840, 2160, &desktop).unwrap();
+        assert_eq!(Some((0, 0)), mapping.to_cursor(0, 0));
+        assert_eq!(Some((1000, 500)), mapping.to_cursor(1500, 750));
+        assert_eq!(Some((2559, 1439)), mapping.to_cursor(3839, 2159));
+
+        assert_eq!(None, ScreenMapping::new(2560, 1440, &desktop));
+        assert_eq!(None, ScreenMapping::new(3840, 2160, &Desktop { dpi: 0, ..desktop }));
+    }
+
+    #[test]
+    fn test_scale_dpi() {
+        assert_eq!(96, scale_dpi(1920, 1920));
+        assert_eq!(144, scale_dpi(3840, 2560));
+        assert_eq!(192, scale_dpi(3840, 1920));
+        assert_eq!(0, scale_dpi(3840, 0));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::img::GrayImage;
+
+/// 8-connected group of non-zero pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Component {
+    pub left: u32,
+    pub top: u32,
+    /// Inclusive, like `GrayImage::rect_hull`
+    pub right: u32,
+    /// Inclusive, like `GrayImage::rect_hull`
+    pub bottom: u32,
+    /// Count of pixels
+    pub area: u32,
+}
+
+impl Component {
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.right - self.left + 1
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.bottom - self.top + 1
+    }
+}
+
+/// Binary morphology with `(2 * radius + 1)` square structuring element.
+/// Each window is counted with summed-area table, so cost does not depend on `radius`
+impl GrayImage {
+    /// Keeps pixel only if the whole window around it is non-zero. Pixels outside the image are ignored
+    pub fn erode(&self, radius: u32) -> GrayImage {
+        self.map_windows(radius, |count, area| count == area)
+    }
+
+    /// Sets pixel if any pixel in the window around it is non-zero
+    pub fn dilate(&self, radius: u32) -> GrayImage {
+        self.map_windows(radius, |count, _| count != 0)
+    }
+
+    /// Erosion followed by dilation. Removes specks smaller than the window
+    pub fn open(&self, radius: u32) -> GrayImage {
+        self.erode(radius).dilate(radius)
+    }
+
+    /// Dilation followed by erosion. Fills gaps narrower than the window
+    pub fn close(&self, radius: u32) -> GrayImage {
+        self.dilate(radius).erode(radius)
+    }
+
+    fn map_windows(&self, radius: u32, set: impl Fn(u32, u32) -> bool) -> GrayImage {
+        let (w, h) = (self.width(), self.height());
+        let mut data = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            let (top, bottom) = (y.saturating_sub(radius), u32::min(y + radius + 1, h));
+            for x in 0..w {
+                let (left, right) = (x.saturating_sub(radius), u32::min(x + radius + 1, w));
+                let area = (right - left) * (bottom - top);
+                data.push(if set(self.count(left, top, right, bottom), area) { 255u8 } else { 0u8 });
+            }
+        }
+        return GrayImage::new(w, h, data);
+    }
+
+    /// Labels 8-connected components of non-zero pixels.
+    /// Components are ordered by their first pixel in row-major order
+    pub fn components(&self) -> Vec<Component> {
+        let (w, h) = (self.width(), self.height());
+        let mut visited = vec![false; (w * h) as usize];
+        let mut stack = Vec::new();
+        let mut result = Vec::new();
+
+        for y in 0..h {
+            for x in 0..w {
+                if visited[(y * w + x) as usize] || self.pixel(x, y) == 0 {
+                    continue;
+                }
+                visited[(y * w + x) as usize] = true;
+                stack.push((x, y));
+                let mut component = Component { left: x, top: y, right: x, bottom: y, area: 0 };
+
+                while let Some((cx, cy)) = stack.pop() {
+                    component.area += 1;
+                    component.left = component.left.min(cx);
+                    component.right = component.right.max(cx);
+                    component.top = component.top.min(cy);
+                    component.bottom = component.bottom.max(cy);
+
+                    for ny in cy.saturating_sub(1)..u32::min(cy + 2, h) {
+                        for nx in cx.saturating_sub(1)..u32::min(cx + 2, w) {
+                            let i = (ny * w + nx) as usize;
+                            if !visited[i] && self.pixel(nx, ny) != 0 {
+                                visited[i] = true;
+                                stack.push((nx, ny));
+                            }
+                        }
+                    }
+                }
+                result.push(component);
+            }
+        }
+        return result;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::img::GrayImage;
+    use crate::morph::Component;
+
+    fn parse(rows: &[&str]) -> GrayImage {
+        let data = rows.iter()
+            .flat_map(|row| row.bytes().map(|b| if b == b'#' { 255u8 } else { 0u8 }))
+            .collect();
+        GrayImage::new(rows[0].len() as u32, rows.len() as u32, data)
+    }
+
+    fn format(img: &GrayImage) -> Vec<String> {
+        (0..img.height())
+            .map(|y| (0..img.width()).map(|x| if img.pixel(x, y) != 0 { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_erode() {
+        let img = parse(&[
+            "#####.",
+            "#####.",
+            "#####.",
+            "....#.",
+        ]);
+        assert_eq!(vec![
+            "####..",
+            "####..",
+            "......",
+            "......",
+        ], format(&img.erode(1)));
+        assert_eq!(format(&img), format(&img.erode(0)));
+    }
+
+    #[test]
+    fn test_dilate() {
+        let img = parse(&[
+            ".....",
+            ".#...",
+            ".....",
+            "....#",
+        ]);
+        assert_eq!(vec![
+            "###..",
+            "###..",
+            "#####",
+            "...##",
+        ], format(&img.dilate(1)));
+    }
+
+    #[test]
+    fn test_open_removes_specks() {
+        let img = parse(&[
+            "#.......",
+            "...####.",
+            "...####.",
+            "...####.",
+            "#.......",
+        ]);
+        assert_eq!(vec![
+            "........",
+            "...####.",
+            "...####.",
+            "...####.",
+            "........",
+        ], format(&img.open(1)));
+    }
+
+    #[test]
+    fn test_close_fills_gaps() {
+        let img = parse(&[
+            "#######",
+            "###.###",
+            "#######",
+        ]);
+        assert_eq!(vec![
+            "#######",
+            "#######",
+            "#######",
+        ], format(&img.close(1)));
+    }
+
+    #[test]
+    fn test_components() {
+        let img = parse(&[
+            "##...#",
+            ".#..#.",
+            "......",
+            "###..#",
+        ]);
+        assert_eq!(vec![
+            Component { left: 0, top: 0, right: 1, bottom: 1, area: 3 },
+            Component { left: 4, top: 0, right: 5, bottom: 1, area: 2 },
+            Component { left: 0, top: 3, right: 2, bottom: 3, area: 3 },
+            Component { left: 5, top: 3, right: 5, bottom: 3, area: 1 },
+        ], img.components());
+
+        let c = img.components()[2];
+        assert_eq!(3, c.width());
+        assert_eq!(1, c.height());
+    }
+
+    #[test]
+    fn test_components_concave() {
+        // U-shape is reached from both branches
+        let img = parse(&[
+            "#...#",
+            "#...#",
+            "#####",
+        ]);
+        assert_eq!(vec![Component { left: 0, top: 0, right: 4, bottom: 2, area: 9 }], img.components());
+        assert!(parse(&["...", "..."]).components().is_empty());
+    }
+}
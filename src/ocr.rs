@@ -1,208 +1,614 @@
+use std::path::Path;
+
 use bmp::{Pixel, px};
 
-use crate::img::GrayImage;
+use crate::dib;
+use crate::img::{GrayImage, into_image, load_mask_from_file};
+use crate::morph::Component;
+use crate::util::strip_comment;
 
 // max space interval in px between 2 characters in same matrix item
 const MAX_CHARACTER_SPACING: u32 = 15;
+// smaller blobs are noise specks
+const MIN_BLOB_AREA: u32 = 4;
+// blob is a `[` or `]` bracket of used cell if its height is at least this times width
+const BRACKET_ASPECT: u32 = 3;
+// thinner bands of banner color are not daemon banners
+const MIN_BANNER_HEIGHT: u32 = 8;
+// banner mask is closed and opened with this radius, so seams are filled and specks are removed
+const BANNER_DENOISE_RADIUS: u32 = 1;
+// cell is highlighted if at least this share of its background matches highlight filter
+const MIN_HIGHLIGHT_SHARE: f64 = 0.5;
+
+// code and its (left, right, bottom) in embedded `template.bmp`
+const EMBEDDED_TEMPLATES: [(u8, u32, u32, u32); 6] = [
+    (0x1C, 0, 23, 19),
+    (0x55, 23, 49, 20),
+    (0x7A, 49, 75, 19),
+    (0xBD, 75, 104, 20),
+    (0xE9, 104, 130, 20),
+    (0xFF, 130, 155, 20),
+];
 
-#[allow(non_snake_case)]
+/// Manifest file name inside template pack directory
+pub const TEMPLATE_MANIFEST: &str = "templates.txt";
+
+/// Code templates, white glyph on black background
 pub struct MatrixTemplates {
-    T_1C: GrayImage,
-    T_55: GrayImage,
-    T_7A: GrayImage,
-    T_BD: GrayImage,
-    T_E9: GrayImage,
-    T_FF: GrayImage,
+    templates: Vec<(u8, GrayImage)>,
 }
 
 impl MatrixTemplates {
+    /// Embedded pack
     pub fn load_templates() -> Self {
         let bytes = include_bytes!("template.bmp");
-        let bmp = bmp::from_reader(&mut bytes.as_ref()).unwrap();
+        let bmp = dib::decode(bytes).unwrap();
 
         let color = px!(255, 255, 255);
-        return Self {
-            T_1C: GrayImage::filter(&bmp, &color, 1, 000, 0, 023, 19),
-            T_55: GrayImage::filter(&bmp, &color, 1, 023, 0, 049, 20),
-            T_7A: GrayImage::filter(&bmp, &color, 1, 049, 0, 075, 19),
-            T_BD: GrayImage::filter(&bmp, &color, 1, 075, 0, 104, 20),
-            T_E9: GrayImage::filter(&bmp, &color, 1, 104, 0, 130, 20),
-            T_FF: GrayImage::filter(&bmp, &color, 1, 130, 0, 155, 20),
+        let templates = EMBEDDED_TEMPLATES.iter()
+            .map(|&(code, left, right, bottom)| (code, GrayImage::filter(&bmp, &color, 1, left, 0, right, bottom)))
+            .collect();
+        return Self { templates };
+    }
+
+    /// Loads pack from directory with `templates.txt` manifest or from manifest file itself.
+    /// Each manifest line is `<hex code> <image path> [scale]`, `#` at line start or after whitespace starts a comment.
+    /// Image paths are relative to manifest. Pixels with any channel brighter than 50% are glyph.
+    /// Template is resized by `scale`, so a pack may be made at another resolution
+    pub fn load_pack<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let manifest_path = if path.is_dir() { path.join(TEMPLATE_MANIFEST) } else { path.to_path_buf() };
+        let dir = manifest_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let manifest = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Template manifest {} was not read: {}", manifest_path.display(), e))?;
+
+        let mut templates = Vec::new();
+        for (i, line) in manifest.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line = || format!("Bad template manifest line {}: {}", i + 1, line);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (code, file, scale) = match parts.as_slice() {
+                &[code, file] => (code, file, "1"),
+                &[code, file, scale] => (code, file, scale),
+                _ => return Err(bad_line()),
+            };
+            let code = u8::from_str_radix(code, 16).map_err(|_| bad_line())?;
+            let scale: f64 = scale.parse().map_err(|_| bad_line())?;
+            if scale.is_nan() || scale <= 0.0 {
+                return Err(bad_line());
+            }
+
+            let template = load_mask_from_file(dir.join(file))?;
+            let template = if scale == 1.0 { template } else { template.scaled(scale) };
+            templates.push((code, template));
+        }
+
+        if templates.is_empty() {
+            return Err(format!("Template manifest {} is empty", manifest_path.display()));
+        }
+        return Ok(Self { templates });
+    }
+
+    pub fn new(templates: Vec<(u8, GrayImage)>) -> Self {
+        Self { templates }
+    }
+
+    /// Saves each template as `<code>.bmp` with `templates.txt` manifest into `dir`
+    pub fn save_pack<P: AsRef<Path>>(&self, dir: P) -> Result<(), String> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| format!("Directory {} was not created: {}", dir.display(), e))?;
+
+        let mut manifest = "# code file scale\n".to_owned();
+        for (code, template) in self.templates.iter() {
+            let file = format!("{:02X}.bmp", code);
+            into_image(template).save(dir.join(&file)).map_err(|e| format!("Template {} was not saved: {}", file, e))?;
+            manifest += &format!("{:02X} {}\n", code, file);
+        }
+        let manifest_path = dir.join(TEMPLATE_MANIFEST);
+        return std::fs::write(&manifest_path, manifest)
+            .map_err(|e| format!("Template manifest {} was not saved: {}", manifest_path.display(), e));
+    }
+
+    /// Glyph of `code` if the pack has it
+    #[cfg(test)]
+    pub fn template(&self, code: u8) -> Option<&GrayImage> {
+        self.templates.iter().find(|(c, _)| *c == code).map(|(_, t)| t)
+    }
+
+    /// Loads pack at `path` if it is set, otherwise embedded pack
+    pub fn load_or_embedded(path: Option<&Path>) -> Result<Self, String> {
+        return match path {
+            Some(path) => Self::load_pack(path),
+            None => Ok(Self::load_templates()),
         };
     }
 }
 
-struct Location {
-    start: u32,
-    end: u32,
+/// Template matcher of `ocr_grid`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMethod {
+    /// Share of mismatched pixels of template stretched to glyph hull
+    #[default]
+    Mismatch,
+    /// Normalized cross-correlation with alignment search. Error is `1 - correlation`
+    Ncc,
 }
 
-/// Returns 2 vectors:
-/// - columns (`x`)
-/// - rows (`y`)
-fn locate_matrix_regions(img: &GrayImage) -> Result<(Vec<Location>, Vec<Location>), String> {
-    // will think that usual matrix/conditions is not greater than 10x10.
-    // matrix_table_* stores x/y coordinates in format (start1, end1, start2, end2, ...)
-    let mut matrix_table_x: Vec<u32> = Vec::with_capacity(10 * 2);
-    let mut matrix_table_y: Vec<u32> = Vec::with_capacity(10 * 2);
-
-    let mut was_space = true;
-    for (x, non_blank) in img.columns_usage().into_iter().enumerate() {
-        if non_blank {
-            if was_space {
-                // new character
-
-                // each item consists of 2 characters
-                let same_item = if let Some(&prev) = matrix_table_x.last() {
-                    x as u32 - prev <= MAX_CHARACTER_SPACING
-                } else {
-                    false
-                };
-                if same_item {
-                    // item is continued. wait for new ending
-                    matrix_table_x.pop();
-                } else {
-                    // new item
-                    matrix_table_x.push(x as _);
-                }
-            } else {
-                // do nothing. same character
-            }
-            was_space = false;
-        } else {
-            if !was_space {
-                // end of character
-                matrix_table_x.push(x as _);
-            }
-            was_space = true;
+impl MatchMethod {
+    /// `mismatch` or `ncc`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "mismatch" => Some(MatchMethod::Mismatch),
+            "ncc" => Some(MatchMethod::Ncc),
+            _ => None,
         }
     }
 
-    let mut was_space = true;
-    for (y, non_blank) in img.rows_usage().into_iter().enumerate() {
-        if non_blank {
-            if was_space {
-                // new character
-                matrix_table_y.push(y as _);
-            } else {
-                // do nothing. same character
-            }
-            was_space = false;
-        } else {
-            if !was_space {
-                // end of character
-                matrix_table_y.push(y as _);
-            }
-            was_space = true;
+    /// Cells matched worse are rejected by default
+    pub fn default_max_error(self) -> f64 {
+        match self {
+            MatchMethod::Mismatch => 0.5,
+            MatchMethod::Ncc => 0.6,
         }
     }
 
-    if matrix_table_x.is_empty() || matrix_table_y.is_empty() {
-        return Err("Matrix items not found".to_owned());
+    /// Lower is better
+    fn error(self, img: &GrayImage, left: u32, top: u32, right: u32, bottom: u32, template: &GrayImage) -> f64 {
+        match self {
+            MatchMethod::Mismatch => img.template_match_error_score(left, top, right, bottom, template),
+            MatchMethod::Ncc => 1.0 - img.template_match_ncc(left, top, right, bottom, template),
+        }
     }
-    if matrix_table_x.len() % 2 != 0 || matrix_table_y.len() % 2 != 0 {
-        return Err("Matrix items were recognized incorrectly".to_owned());
+}
+
+/// Matcher and rejection threshold of `ocr_matrix_state`/`ocr_daemons`/`ocr_buffer`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OcrOptions {
+    pub method: MatchMethod,
+    /// Recognition fails if the best match error of any cell is greater
+    pub max_error: f64,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        Self::new(MatchMethod::default())
+    }
+}
+
+impl OcrOptions {
+    pub fn new(method: MatchMethod) -> Self {
+        Self { method, max_error: method.default_max_error() }
+    }
+}
+
+/// Recognized cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellMatch {
+    pub code: u8,
+    /// Match error of the best template
+    pub error: f64,
+    /// Match error of the second-best template. Infinite if there is single template
+    pub second_error: f64,
+}
+
+impl CellMatch {
+    /// `0..=1`. 0 means the best and the second-best templates match equally
+    pub fn confidence(&self) -> f64 {
+        if self.second_error.is_infinite() {
+            return 1.0;
+        }
+        if self.second_error <= 0.0 {
+            return 0.0;
+        }
+        return (1.0 - self.error / self.second_error).clamp(0.0, 1.0);
     }
+}
 
-    let columns = (0..(matrix_table_x.len() / 2))
-        .map(|i| Location { start: matrix_table_x[i * 2], end: matrix_table_x[i * 2 + 1] })
+/// Codes of recognized cells
+pub fn codes(cells: &[Vec<CellMatch>]) -> Vec<Vec<u8>> {
+    cells.iter().map(|row| row.iter().map(|c| c.code).collect()).collect()
+}
+
+pub struct Location {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Matrix/conditions grid built from glyph blobs
+pub struct Segmentation {
+    pub columns: Vec<Location>,
+    pub rows: Vec<Location>,
+    /// `(column, row)` cells, which don't consist of exactly 2 glyphs (missing, merged or broken characters).
+    /// They are still recognized, but the result may be wrong
+    pub uncertain: Vec<(usize, usize)>,
+}
+
+/// Groups connected components into grid cells. Specks, thin lines and lone blobs are ignored,
+/// so a stray pixel or cursor does not break the whole grid
+pub fn segment_glyphs(img: &GrayImage) -> Result<Segmentation, String> {
+    let blobs: Vec<Component> = img.components().into_iter()
+        .filter(|b| b.area >= MIN_BLOB_AREA)
+        .collect();
+
+    // glyph is at least half as high as the highest blob line
+    let lines = merge_ranges(blobs.iter().map(|b| (b.top, b.bottom + 1)), 0);
+    let max_height = lines.iter().map(|l| l.end - l.start).max().unwrap_or(0);
+    let glyphs: Vec<&Component> = blobs.iter()
+        .filter(|b| b.height() * 2 >= max_height)
         .collect();
-    let rows = (0..(matrix_table_y.len() / 2))
-        .map(|i| Location { start: matrix_table_y[i * 2], end: matrix_table_y[i * 2 + 1] })
+
+    // each row and column has at least 1 item of 2 glyphs, otherwise it is a lone blob
+    let rows: Vec<Location> = merge_ranges(glyphs.iter().map(|g| (g.top, g.bottom + 1)), 0)
+        .into_iter()
+        .filter(|r| glyphs.iter().filter(|g| r.start <= g.top && g.bottom < r.end).count() >= 2)
+        .collect();
+    let glyphs: Vec<&Component> = glyphs.into_iter()
+        .filter(|g| rows.iter().any(|r| r.start <= g.top && g.bottom < r.end))
+        .collect();
+    let columns: Vec<Location> = merge_ranges(glyphs.iter().map(|g| (g.left, g.right + 1)), MAX_CHARACTER_SPACING)
+        .into_iter()
+        .filter(|c| glyphs.iter().filter(|g| c.start <= g.left && g.right < c.end).count() >= 2)
+        .collect();
+    if columns.is_empty() || rows.is_empty() {
+        return Err("Matrix items not found".to_owned());
+    }
+
+    let mut counts = vec![vec![0usize; columns.len()]; rows.len()];
+    for g in glyphs.iter() {
+        let column = columns.iter().position(|c| c.start <= g.left && g.right < c.end);
+        let row = rows.iter().position(|r| r.start <= g.top && g.bottom < r.end);
+        if let (Some(column), Some(row)) = (column, row) {
+            counts[row][column] += 1;
+        }
+    }
+    let uncertain = counts.iter().enumerate()
+        .flat_map(|(row, line)| {
+            line.iter().enumerate()
+                .filter(|(_, &count)| count != 0 && count != 2)
+                .map(move |(column, _)| (column, row))
+        })
         .collect();
-    return Ok((columns, rows));
+
+    return Ok(Segmentation { columns, rows, uncertain });
+}
+
+/// Merges `start..end` ranges, which are separated by not more than `max_gap`
+fn merge_ranges(ranges: impl Iterator<Item=(u32, u32)>, max_gap: u32) -> Vec<Location> {
+    let mut ranges: Vec<(u32, u32)> = ranges.collect();
+    ranges.sort_unstable();
+
+    let mut result: Vec<Location> = Vec::new();
+    for (start, end) in ranges {
+        match result.last_mut() {
+            Some(last) if start <= last.end + max_gap => last.end = last.end.max(end),
+            _ => result.push(Location { start, end }),
+        }
+    }
+    return result;
 }
 
-fn ocr_matrix_item(img: &GrayImage, templates: &MatrixTemplates, column: &Location, row: &Location) -> Option<u8> {
+/// Returns recognized code with the best and the second-best template match errors
+fn ocr_matrix_item(img: &GrayImage, luma: Option<&GrayImage>, templates: &MatrixTemplates, method: MatchMethod, column: &Location, row: &Location) -> Option<CellMatch> {
+    ocr_rect(img, luma, templates, method, column.start, row.start, column.end - 1, row.end - 1)
+}
+
+/// Matches glyph hull inside inclusive `left`, `top`, `right`, `bottom` area. `None` means empty area.
+/// `MatchMethod::Ncc` matches `luma` instead of `img` mask, if it is given
+fn ocr_rect(img: &GrayImage, luma: Option<&GrayImage>, templates: &MatrixTemplates, method: MatchMethod, left: u32, top: u32, right: u32, bottom: u32) -> Option<CellMatch> {
 
-    let (x_start, y_start, x_end, y_end) = match img.rect_hull(column.start, row.start, column.end - 1, row.end - 1) {
+    let (x_start, y_start, x_end, y_end) = match img.rect_hull(left, top, right, bottom) {
         Some(v) => v,
         None => return None,
     };
 
-    return Some([
-        (0x1Cu8, img.template_match_error_score(x_start, y_start, x_end, y_end, &templates.T_1C)),
-        (0x55u8, img.template_match_error_score(x_start, y_start, x_end, y_end, &templates.T_55)),
-        (0x7Au8, img.template_match_error_score(x_start, y_start, x_end, y_end, &templates.T_7A)),
-        (0xBDu8, img.template_match_error_score(x_start, y_start, x_end, y_end, &templates.T_BD)),
-        (0xE9u8, img.template_match_error_score(x_start, y_start, x_end, y_end, &templates.T_E9)),
-        (0xFFu8, img.template_match_error_score(x_start, y_start, x_end, y_end, &templates.T_FF)),
-    ].into_iter()
-        .min_by(|(_, error1), (_, error2)| error1.partial_cmp(error2).unwrap())
-        .unwrap().0);
+    let pixels = match method {
+        MatchMethod::Mismatch => img,
+        MatchMethod::Ncc => luma.unwrap_or(img),
+    };
+    let mut errors: Vec<(u8, f64)> = templates.templates.iter()
+        .map(|(code, template)| (*code, method.error(pixels, x_start, y_start, x_end, y_end, template)))
+        .collect();
+    errors.sort_by(|(_, error1), (_, error2)| error1.total_cmp(error2));
+
+    let &(code, error) = errors.first()?;
+    let second_error = errors.get(1).map(|&(_, e)| e).unwrap_or(f64::INFINITY);
+    return Some(CellMatch { code, error, second_error });
 }
 
-pub fn ocr_matrix(img: &GrayImage, templates: &MatrixTemplates) -> Result<(u32, u32, u32, u32, Vec<Vec<u8>>), String> {
+/// Recognizes each `columns` x `rows` cell. `None` means empty cell.
+/// `luma` is grayscale of the same area as `img` mask, see `ocr_rect`
+pub fn ocr_grid(img: &GrayImage, luma: Option<&GrayImage>, templates: &MatrixTemplates, method: MatchMethod, columns: &[Location], rows: &[Location]) -> Vec<Vec<Option<CellMatch>>> {
+    rows.iter().map(|row| {
+        columns.iter()
+            .map(|column| ocr_matrix_item(img, luma, templates, method, column, row))
+            .collect()
+    }).collect()
+}
 
-    let (columns, rows) = match locate_matrix_regions(&img) {
-        Ok(x) => x,
-        Err(e) => return Err(e),
-    };
+/// Matrix cell during breach
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellState {
+    Code(CellMatch),
+    /// Already selected cell, shown as `[ ]`
+    Used,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatrixCell {
+    pub state: CellState,
+    /// Cell background belongs to the active line
+    pub highlighted: bool,
+}
+
+/// Row or column, where the next cell must be selected. Indexes are 0-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActiveLine {
+    Row(usize),
+    Column(usize),
+}
+
+/// Matrix recognized at any breach step
+pub struct MatrixState {
+    /// Bounds (`left`, `top`, `right`, `bottom`) of the first and the last items
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub cells: Vec<Vec<MatrixCell>>,
+    /// `None` if no line is highlighted
+    pub active_line: Option<ActiveLine>,
+    /// `(column, row)` of code cells with unexpected count of glyphs. Their codes may be wrong
+    pub uncertain: Vec<(usize, usize)>,
+    /// Bands of `cells` including restored fully used ones
+    pub columns: Vec<Location>,
+    pub rows: Vec<Location>,
+}
+
+impl MatrixState {
+    /// Recognized cells if no cell is used yet
+    pub fn code_cells(&self) -> Option<Vec<Vec<CellMatch>>> {
+        self.cells.iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| match c.state {
+                        CellState::Code(cell) => Some(cell),
+                        CellState::Used => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Codes of all cells, `None` for used ones
+    pub fn cell_codes(&self) -> Vec<Vec<Option<u8>>> {
+        self.cells.iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| match c.state {
+                        CellState::Code(cell) => Some(cell.code),
+                        CellState::Used => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn used_count(&self) -> usize {
+        self.cells.iter().flatten().filter(|c| c.state == CellState::Used).count()
+    }
+}
+
+/// Recognizes matrix at any breach step with used cells and the active line.
+/// `highlight` is mask of the highlighted background of the same area as `img`.
+/// `luma` is optional grayscale of the same area for `MatchMethod::Ncc`.
+/// Fully used inner rows/columns are restored by the grid pitch, fully used edge ones can't be found
+pub fn ocr_matrix_state(img: &GrayImage, highlight: &GrayImage, luma: Option<&GrayImage>, templates: &MatrixTemplates, options: &OcrOptions) -> Result<MatrixState, String> {
+    debug_assert_eq!(img.width(), highlight.width());
+    debug_assert_eq!(img.height(), highlight.height());
+
+    let Segmentation { columns, rows, uncertain } = segment_glyphs(&img)?;
+    let columns = fill_gaps(columns);
+    let rows = fill_gaps(rows);
 
     if columns.len() < 3 || columns.len() != rows.len() {
         return Err("Bad matrix dimension".to_owned());
     }
 
-    let matrix_dimension = columns.len();
     let left = columns.first().unwrap().start;
     let right = columns.last().unwrap().start;
     let top = rows.first().unwrap().start;
     let bottom = rows.last().unwrap().start;
-    let mut result: Vec<Vec<u8>> = Vec::with_capacity(matrix_dimension);
-
-    for row in rows.iter() {
-        let mut matrix_row = Vec::with_capacity(matrix_dimension);
-        for column in columns.iter() {
-            let matrix_item = match ocr_matrix_item(&img, &templates, &column, &row) {
-                Some(v) => v,
-                None => return Err("One of matrix items was not recognized".to_owned()),
+    let blobs: Vec<Component> = img.components().into_iter()
+        .filter(|b| b.area >= MIN_BLOB_AREA)
+        .collect();
+    let mut cells: Vec<Vec<MatrixCell>> = Vec::with_capacity(rows.len());
+
+    for (y, (row, grid_row)) in rows.iter().zip(ocr_grid(&img, luma, &templates, options.method, &columns, &rows)).enumerate() {
+        let mut matrix_row = Vec::with_capacity(columns.len());
+        for (x, (column, grid_item)) in columns.iter().zip(grid_row).enumerate() {
+            let cell_blobs: Vec<&Component> = blobs.iter()
+                .filter(|b| column.start <= b.left && b.right < column.end && row.start <= b.top && b.bottom < row.end)
+                .collect();
+            let brackets = !cell_blobs.is_empty() && cell_blobs.iter().all(|b| b.width() * BRACKET_ASPECT <= b.height());
+            let state = match grid_item {
+                Some(_) if brackets => CellState::Used,
+                Some(cell) => CellState::Code(check_error("Matrix", cell, x, y, options)?),
+                None => CellState::Used,
             };
-            matrix_row.push(matrix_item);
+
+            let area = (column.end - column.start) * (row.end - row.start);
+            let background = area - img.count(column.start, row.start, column.end, row.end);
+            let lit = highlight.count(column.start, row.start, column.end, row.end);
+            let highlighted = background != 0 && lit as f64 >= background as f64 * MIN_HIGHLIGHT_SHARE;
+            matrix_row.push(MatrixCell { state, highlighted });
         }
-        result.push(matrix_row);
+        cells.push(matrix_row);
     }
 
-    debug_assert_eq!(matrix_dimension, result.len());
-    debug_assert_eq!(matrix_dimension, result[0].len());
-    debug_assert_eq!(matrix_dimension, result[1].len());
-    return Ok((left, top, right, bottom, result));
+    let active_line = (0..rows.len()).find(|&y| cells[y].iter().all(|c| c.highlighted)).map(ActiveLine::Row)
+        .or_else(|| (0..columns.len()).find(|&x| cells.iter().all(|row| row[x].highlighted)).map(ActiveLine::Column));
+    let uncertain: Vec<(usize, usize)> = uncertain.into_iter()
+        .filter(|&(x, y)| cells[y][x].state != CellState::Used)
+        .collect();
+    return Ok(MatrixState { left, top, right, bottom, cells, active_line, uncertain, columns, rows });
 }
 
-pub fn ocr_conditions(img: &GrayImage, templates: &MatrixTemplates) -> Result<Vec<Vec<u8>>, String> {
-
-    let (columns, rows) = match locate_matrix_regions(&img) {
-        Ok(x) => x,
-        Err(e) => return Err(e),
+/// Inserts locations into gaps, which are multiple of the smallest pitch between neighbours
+fn fill_gaps(locations: Vec<Location>) -> Vec<Location> {
+    let pitch = match locations.windows(2).map(|w| w[1].start - w[0].start).min() {
+        Some(p) if p > 0 => p,
+        _ => return locations,
     };
-
-    if columns.is_empty() || rows.is_empty() {
-        return Err("Bad condition dimension".to_owned());
+    let mut result: Vec<Location> = Vec::with_capacity(locations.len());
+    for location in locations {
+        if let Some(last) = result.last() {
+            let (start, size) = (last.start, last.end - last.start);
+            let missing = ((location.start - start) as f64 / pitch as f64).round() as u32;
+            for i in 1..missing {
+                result.push(Location { start: start + i * pitch, end: start + i * pitch + size });
+            }
+        }
+        result.push(location);
     }
+    return result;
+}
+
+/// Daemon progress during breach
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaemonStatus {
+    Pending,
+    Installed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Daemon {
+    pub status: DaemonStatus,
+    /// Empty for finished daemons, their codes are covered with banner
+    pub codes: Vec<CellMatch>,
+    /// Count of columns the codes are shifted right by as the buffer fills.
+    /// Relative to the leftmost code column of the panel
+    pub offset: usize,
+    /// Rows `top..bottom` of the codes or the banner
+    pub top: u32,
+    pub bottom: u32,
+    /// Columns of codes with unexpected count of glyphs, relative like `offset`. Their codes may be wrong
+    pub uncertain: Vec<usize>,
+}
+
+/// Reads daemons from top to bottom. `installed` and `failed` are banner masks of the same area as `img`
+pub fn ocr_daemons(img: &GrayImage, installed: &GrayImage, failed: &GrayImage, templates: &MatrixTemplates, options: &OcrOptions) -> Result<Vec<Daemon>, String> {
+    debug_assert_eq!((img.width(), img.height()), (installed.width(), installed.height()));
+    debug_assert_eq!((img.width(), img.height()), (failed.width(), failed.height()));
 
-    let mut result: Vec<Vec<u8>> = Vec::with_capacity(rows.len());
+    let mut banners: Vec<(Location, DaemonStatus)> = find_banners(installed).into_iter()
+        .map(|b| (b, DaemonStatus::Installed))
+        .chain(find_banners(failed).into_iter().map(|b| (b, DaemonStatus::Failed)))
+        .collect();
+    banners.sort_by_key(|(b, _)| b.start);
 
-    for row in rows.iter() {
-        let mut condition_row = Vec::with_capacity(columns.len());
-        for column in columns.iter() {
-            let matrix_item = match ocr_matrix_item(&img, &templates, &column, &row) {
-                Some(v) => v,
+    // banner text is not a code
+    let (w, h) = (img.width(), img.height());
+    let in_banner = |y: u32| banners.iter().any(|(b, _)| b.start <= y && y < b.end);
+    let data = (0..h)
+        .flat_map(|y| {
+            let skip = in_banner(y);
+            (0..w).map(move |x| if skip { 0 } else { img.pixel(x, y) })
+        })
+        .collect();
+    let codes_img = GrayImage::new(w, h, data);
+
+    let mut result: Vec<(u32, Daemon)> = banners.iter()
+        .map(|(b, status)| (b.start, Daemon { status: *status, codes: Vec::new(), offset: 0, top: b.start, bottom: b.end, uncertain: Vec::new() }))
+        .collect();
+    let Segmentation { columns, rows, uncertain } = match segment_glyphs(&codes_img) {
+        Ok(s) => s,
+        Err(_) if !result.is_empty() => Segmentation { columns: Vec::new(), rows: Vec::new(), uncertain: Vec::new() },
+        Err(_) => return Err("Bad condition dimension".to_owned()),
+    };
+    let columns = fill_gaps(columns);
+
+    for (y, (row, grid_row)) in rows.iter().zip(ocr_grid(&codes_img, None, &templates, options.method, &columns, &rows)).enumerate() {
+        let offset = grid_row.iter().position(Option::is_some).unwrap_or(0);
+        let mut codes = Vec::with_capacity(columns.len());
+        for (x, grid_item) in grid_row.into_iter().enumerate().skip(offset) {
+            match grid_item {
+                Some(cell) => codes.push(check_error("Condition", cell, x, y, options)?),
                 None => break, // short condition. Goto next row
             };
-            condition_row.push(matrix_item);
         }
-        result.push(condition_row);
+        let uncertain = uncertain.iter()
+            .filter(|&&(ux, uy)| uy == y && offset <= ux && ux < offset + codes.len())
+            .map(|&(ux, _)| ux)
+            .collect();
+        result.push((row.start, Daemon { status: DaemonStatus::Pending, codes, offset, top: row.start, bottom: row.end, uncertain }));
     }
 
-    debug_assert_eq!(rows.len(), result.len());
+    result.sort_by_key(|(top, _)| *top);
+    return Ok(result.into_iter().map(|(_, d)| d).collect());
+}
+
+/// Horizontal bands, where the most of each line is filled
+fn find_banners(mask: &GrayImage) -> Vec<Location> {
+    let mask = mask.close(BANNER_DENOISE_RADIUS).open(BANNER_DENOISE_RADIUS);
+    let (w, h) = (mask.width(), mask.height());
+    let lines = (0..h)
+        .filter(|&y| mask.count(0, y, w, y + 1) * 2 >= w)
+        .map(|y| (y, y + 1));
+    merge_ranges(lines, 1).into_iter()
+        .filter(|b| b.end - b.start >= MIN_BANNER_HEIGHT)
+        .collect()
+}
+
+/// Recognizes code in each buffer slot. `slots` are inner `(left, right)` bounds (inclusive) spanning the whole `img` height.
+/// `None` means empty slot, noise specks are ignored. Filled slots must be a prefix, since the game fills the buffer from the left
+pub fn ocr_buffer(img: &GrayImage, slots: &[(u32, u32)], templates: &MatrixTemplates, options: &OcrOptions) -> Result<Vec<Option<u8>>, String> {
+    let blobs: Vec<Component> = img.components().into_iter()
+        .filter(|b| b.area >= MIN_BLOB_AREA)
+        .collect();
+    let mut result = Vec::with_capacity(slots.len());
+    for (x, &(left, right)) in slots.iter().enumerate() {
+        let slot_blobs: Vec<&Component> = blobs.iter().filter(|b| left <= b.left && b.right <= right).collect();
+        let code = if slot_blobs.is_empty() {
+            None
+        } else {
+            let top = slot_blobs.iter().map(|b| b.top).min().unwrap();
+            let bottom = slot_blobs.iter().map(|b| b.bottom).max().unwrap();
+            let left = slot_blobs.iter().map(|b| b.left).min().unwrap();
+            let right = slot_blobs.iter().map(|b| b.right).max().unwrap();
+            match ocr_rect(img, None, templates, options.method, left, top, right, bottom) {
+                Some(cell) => Some(check_error("Buffer", cell, x, 0, options)?.code),
+                None => None,
+            }
+        };
+        if code.is_some() && result.last() == Some(&None) {
+            return Err(format!("Buffer slot {} is filled after an empty one", x + 1));
+        }
+        result.push(code);
+    }
     return Ok(result);
 }
 
+/// Rejects cell, which matches no template well (e.g. mouse cursor)
+fn check_error(name: &str, cell: CellMatch, x: usize, y: usize, options: &OcrOptions) -> Result<CellMatch, String> {
+    if cell.error > options.max_error {
+        return Err(format!(
+            "{} item at column {} row {} matches no template: error {:.3} > {:.3}",
+            name, x + 1, y + 1, cell.error, options.max_error,
+        ));
+    }
+    return Ok(cell);
+}
+
 
 #[cfg(test)]
 mod tests {
-    use crate::img::{GrayImage, load_img_from_file};
-    use crate::ocr::{MatrixTemplates, ocr_conditions, ocr_matrix};
-    use crate::recognize::{CONDITION_COLOR, MATRIX_COLOR};
+    use crate::img::{GrayImage, into_image, load_img_from_file};
+    use crate::ocr::{ActiveLine, CellMatch, CellState, codes, DaemonStatus, MatchMethod, MatrixTemplates, ocr_matrix_state, OcrOptions, find_banners, ocr_buffer, ocr_daemons, ocr_rect, segment_glyphs, TEMPLATE_MANIFEST};
+    use crate::recognize::{read_daemons, read_matrix, StageFilters};
     use crate::test_cases::{CONDITION_AREA1, CONDITION_AREA2, CONDITION_AREA3, CONDITION_AREA4, CONDITION_AREA5, conditions1, conditions2, conditions3, conditions4, conditions5, FILE1, FILE2, FILE3, FILE4, FILE5, matrix1, matrix2, matrix3, matrix4, matrix5, MATRIX_AREA1, MATRIX_AREA2, MATRIX_AREA3, MATRIX_AREA4, MATRIX_AREA5};
 
     #[test]
@@ -232,11 +638,11 @@ mod tests {
 
     fn test_ocr_matrix(filename: &str, matrix_area: (u32, u32, u32, u32), expected: (u32, u32, u32, u32, Vec<Vec<u8>>)) {
         let templates = MatrixTemplates::load_templates();
-        let img = load_img_from_file(filename);
-        let img = GrayImage::filter(&img, &MATRIX_COLOR, 50, matrix_area.0, matrix_area.1, matrix_area.2, matrix_area.3);
+        let img = load_img_from_file(filename).unwrap();
 
-        let result = ocr_matrix(&img, &templates);
-        assert_eq!(Ok(expected), result);
+        let state = read_matrix(&img, &matrix_area, &StageFilters::default(), &templates, &OcrOptions::default()).unwrap();
+        let cells = state.code_cells().expect("Matrix has used cells");
+        assert_eq!(expected, (state.left, state.top, state.right, state.bottom, codes(&cells)));
     }
 
     #[test]
@@ -266,10 +672,447 @@ mod tests {
 
     fn test_ocr_conditions(filename: &str, condition_area: (u32, u32, u32, u32), expected: Vec<Vec<u8>>) {
         let templates = MatrixTemplates::load_templates();
-        let img = load_img_from_file(filename);
-        let img = GrayImage::filter(&img, &CONDITION_COLOR, 50, condition_area.0, condition_area.1, condition_area.2, condition_area.3);
+        let img = load_img_from_file(filename).unwrap();
+
+        let daemons = read_daemons(&img, &condition_area, &StageFilters::default(), &templates, &OcrOptions::default()).unwrap();
+        let actual: Vec<Vec<CellMatch>> = daemons.into_iter().map(|d| d.codes).collect();
+        assert_eq!(expected, codes(&actual));
+    }
+
+    // 3x3 grid of items, each item is 2 glyphs of 4x8 px with 3 px spacing
+    fn glyph_grid(skip: &[(u32, u32, u32)]) -> Vec<u8> {
+        let (w, h) = (100, 70);
+        let mut data = vec![0u8; w * h];
+        for row in 0..3 {
+            for column in 0..3 {
+                for glyph in 0..2 {
+                    if skip.contains(&(column, row, glyph)) {
+                        continue;
+                    }
+                    let (left, top) = (10 + column * 30 + glyph * 7, 5 + row * 20);
+                    fill(&mut data, w, (left, top, left + 4, top + 8));
+                }
+            }
+        }
+        return data;
+    }
+
+    fn fill(data: &mut [u8], w: usize, (left, top, right, bottom): (u32, u32, u32, u32)) {
+        for y in top..bottom {
+            for x in left..right {
+                data[y as usize * w + x as usize] = 255;
+            }
+        }
+    }
+
+    fn ranges(locations: &[crate::ocr::Location]) -> Vec<(u32, u32)> {
+        locations.iter().map(|l| (l.start, l.end)).collect()
+    }
+
+    #[test]
+    fn test_segment_glyphs() {
+        let img = GrayImage::new(100, 70, glyph_grid(&[]));
+        let result = segment_glyphs(&img).unwrap();
+        assert_eq!(vec![(10, 21), (40, 51), (70, 81)], ranges(&result.columns));
+        assert_eq!(vec![(5, 13), (25, 33), (45, 53)], ranges(&result.rows));
+        assert!(result.uncertain.is_empty());
+    }
+
+    #[test]
+    fn test_segment_glyphs_ignores_noise() {
+        let mut data = glyph_grid(&[]);
+        fill(&mut data, 100, (0, 0, 1, 1)); // speck
+        fill(&mut data, 100, (92, 17, 94, 19)); // speck between rows and columns
+        fill(&mut data, 100, (10, 14, 81, 15)); // thin line under the first row
+        fill(&mut data, 100, (28, 60, 32, 68)); // lone glyph-like blob
+        let img = GrayImage::new(100, 70, data);
+
+        let result = segment_glyphs(&img).unwrap();
+        assert_eq!(vec![(10, 21), (40, 51), (70, 81)], ranges(&result.columns));
+        assert_eq!(vec![(5, 13), (25, 33), (45, 53)], ranges(&result.rows));
+        assert!(result.uncertain.is_empty());
+    }
+
+    #[test]
+    fn test_segment_glyphs_missing_glyph() {
+        let img = GrayImage::new(100, 70, glyph_grid(&[(1, 1, 0)]));
+        let result = segment_glyphs(&img).unwrap();
+        assert_eq!(3, result.columns.len());
+        assert_eq!(3, result.rows.len());
+        assert_eq!(vec![(1, 1)], result.uncertain);
+    }
+
+    #[test]
+    fn test_segment_glyphs_merged_glyphs() {
+        let mut data = glyph_grid(&[]);
+        fill(&mut data, 100, (74, 46, 77, 48)); // joins glyphs of the last cell
+        let img = GrayImage::new(100, 70, data);
+        let result = segment_glyphs(&img).unwrap();
+        assert_eq!(3, result.columns.len());
+        assert_eq!(vec![(2, 2)], result.uncertain);
+    }
 
-        let result = ocr_conditions(&img, &templates);
-        assert_eq!(Ok(expected), result);
+    #[test]
+    fn test_segment_glyphs_empty() {
+        let img = GrayImage::new(10, 10, vec![0; 100]);
+        assert!(segment_glyphs(&img).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ocr_matrix_from_templates() {
+        // 3x3 matrix drawn with template glyphs
+        let templates = MatrixTemplates::load_templates();
+        let expected = vec![
+            vec![0x1C, 0x55, 0x7A],
+            vec![0xBD, 0xE9, 0xFF],
+            vec![0x55, 0xFF, 0x1C],
+        ];
+        let template = |code: u8| &templates.templates.iter().find(|(c, _)| *c == code).unwrap().1;
+
+        let (w, h) = (150u32, 110u32);
+        let mut data = vec![0u8; (w * h) as usize];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, &code) in row.iter().enumerate() {
+                let t = template(code);
+                let (left, top) = (5 + x as u32 * 50, 5 + y as u32 * 35);
+                for ty in 0..t.height() {
+                    for tx in 0..t.width() {
+                        data[((top + ty) * w + left + tx) as usize] = t.pixel(tx, ty);
+                    }
+                }
+            }
+        }
+        let img = GrayImage::new(w, h, data.clone());
+        let no_highlight = GrayImage::new(w, h, vec![0; (w * h) as usize]);
+
+        for method in [MatchMethod::Mismatch, MatchMethod::Ncc] {
+            let state = ocr_matrix_state(&img, &no_highlight, None, &templates, &OcrOptions::new(method)).unwrap();
+            let cells = state.code_cells().unwrap();
+            assert_eq!(expected, codes(&cells), "{:?}", method);
+            assert!(cells.iter().flatten().all(|c| c.error < c.second_error && c.confidence() > 0.0), "{:?}", method);
+        }
+
+        // cursor-like blob in the last cell matches no template well
+        let mut data = data;
+        let (left, top) = (5 + 2 * 50, 5 + 2 * 35);
+        for y in 0..25 {
+            for x in 0..30 {
+                data[((top + y) * w + left + x) as usize] = if x <= y && y < 20 { 255 } else { 0 };
+            }
+        }
+        let img = GrayImage::new(w, h, data);
+        let strict = OcrOptions { method: MatchMethod::Mismatch, max_error: 0.1 };
+        let err = ocr_matrix_state(&img, &no_highlight, None, &templates, &strict).err().expect("Cursor blob was recognized");
+        assert!(err.contains("column 3 row 3"), "{}", err);
+    }
+
+    #[test]
+    fn test_ocr_rect_speck() {
+        // one bright pixel in otherwise empty cell matches no template
+        let templates = MatrixTemplates::load_templates();
+        let mut data = vec![0u8; 30 * 30];
+        data[15 * 30 + 12] = 255;
+        let img = GrayImage::new(30, 30, data);
+        for method in [MatchMethod::Mismatch, MatchMethod::Ncc] {
+            let cell = ocr_rect(&img, None, &templates, method, 0, 0, 29, 29).unwrap();
+            assert!(!cell.error.is_nan(), "{:?}", method);
+            assert!(cell.error > OcrOptions::new(method).max_error, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn test_ocr_matrix_ncc_luma() {
+        // 3x3 matrix, which lost half of glyph pixels to thresholding. Grayscale has them, but dimmed
+        let templates = MatrixTemplates::load_templates();
+        let expected = vec![
+            vec![0x1C, 0x55, 0x7A],
+            vec![0xBD, 0xE9, 0xFF],
+            vec![0x55, 0xFF, 0x1C],
+        ];
+        let template = |code: u8| &templates.templates.iter().find(|(c, _)| *c == code).unwrap().1;
+
+        let (w, h) = (150u32, 110u32);
+        let mut mask = vec![0u8; (w * h) as usize];
+        let mut luma = vec![30u8; (w * h) as usize];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, &code) in row.iter().enumerate() {
+                let t = template(code);
+                let (left, top) = (5 + x as u32 * 50, 5 + y as u32 * 35);
+                for ty in 0..t.height() {
+                    for tx in 0..t.width() {
+                        let i = ((top + ty) * w + left + tx) as usize;
+                        let lost = (tx + ty) % 2 == 1;
+                        mask[i] = if lost { 0 } else { t.pixel(tx, ty) };
+                        luma[i] = 30 + (t.pixel(tx, ty) as u32 * 150 / 255) as u8;
+                    }
+                }
+            }
+        }
+        let mask = GrayImage::new(w, h, mask);
+        let luma = GrayImage::new(w, h, luma);
+        let no_highlight = GrayImage::new(w, h, vec![0; (w * h) as usize]);
+        let options = OcrOptions::new(MatchMethod::Ncc);
+
+        let with_luma = ocr_matrix_state(&mask, &no_highlight, Some(&luma), &templates, &options).unwrap();
+        let without = ocr_matrix_state(&mask, &no_highlight, None, &templates, &options).unwrap();
+        let cells = with_luma.code_cells().unwrap();
+        assert_eq!(expected, codes(&cells));
+        for (a, b) in cells.iter().flatten().zip(without.code_cells().unwrap().iter().flatten()) {
+            assert!(a.error < b.error, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_ocr_matrix_state() {
+        // 4x4 matrix drawn with template glyphs, the whole column 1 and cell (2, 0) are used
+        let templates = MatrixTemplates::load_templates();
+        let expected = vec![
+            vec![0x1C, 0x55, 0x7A, 0xBD],
+            vec![0xBD, 0xE9, 0xFF, 0x1C],
+            vec![0x55, 0xFF, 0x1C, 0xE9],
+            vec![0x7A, 0x1C, 0xE9, 0x55],
+        ];
+        let template = |code: u8| &templates.templates.iter().find(|(c, _)| *c == code).unwrap().1;
+
+        let (w, h) = (200u32, 145u32);
+        let mut data = vec![0u8; (w * h) as usize];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, &code) in row.iter().enumerate() {
+                let (left, top) = (5 + x as u32 * 50, 5 + y as u32 * 35);
+                if x == 1 {
+                    continue;
+                }
+                if (x, y) == (2, 0) {
+                    fill(&mut data, w as usize, (left + 2, top, left + 4, top + 20));
+                    fill(&mut data, w as usize, (left + 20, top, left + 22, top + 20));
+                    continue;
+                }
+                let t = template(code);
+                for ty in 0..t.height() {
+                    for tx in 0..t.width() {
+                        data[((top + ty) * w + left + tx) as usize] = t.pixel(tx, ty);
+                    }
+                }
+            }
+        }
+        // column 2 background is highlighted
+        let highlight = (0..w * h)
+            .map(|i| if (100..140).contains(&(i % w)) && data[i as usize] == 0 { 255 } else { 0 })
+            .collect();
+        let img = GrayImage::new(w, h, data);
+        let highlight = GrayImage::new(w, h, highlight);
+
+        let state = ocr_matrix_state(&img, &highlight, None, &templates, &OcrOptions::default()).unwrap();
+        assert_eq!(4, state.cells.len());
+        assert_eq!(Some(ActiveLine::Column(2)), state.active_line);
+        assert_eq!(5, state.used_count());
+        assert!(state.code_cells().is_none());
+        assert_eq!(Some(expected[0][0]), state.cell_codes()[0][0]);
+        assert_eq!(None, state.cell_codes()[0][1]);
+        assert!(state.uncertain.is_empty());
+        for (y, row) in state.cells.iter().enumerate() {
+            assert_eq!(4, row.len());
+            for (x, cell) in row.iter().enumerate() {
+                assert_eq!(x == 2, cell.highlighted, "column {} row {}", x, y);
+                match cell.state {
+                    CellState::Code(c) => assert_eq!(expected[y][x], c.code, "column {} row {}", x, y),
+                    CellState::Used => assert!(x == 1 || (x, y) == (2, 0), "column {} row {}", x, y),
+                }
+            }
+        }
+
+        // fresh matrix
+        let state = ocr_matrix_state(&img, &GrayImage::new(w, h, vec![0; (w * h) as usize]), None, &templates, &OcrOptions::default());
+        assert!(state.unwrap().active_line.is_none());
+    }
+
+    #[test]
+    fn test_ocr_buffer() {
+        // 4 slots of 35 px, the first 2 are filled
+        let templates = MatrixTemplates::load_templates();
+        let template = |code: u8| &templates.templates.iter().find(|(c, _)| *c == code).unwrap().1;
+        let slots = [(0, 34), (40, 74), (80, 114), (120, 154)];
+        let (w, h) = (155u32, 30u32);
+        let draw = |codes: &[(usize, u8)], specks: &[(u32, u32)]| {
+            let mut data = vec![0u8; (w * h) as usize];
+            for &(x, y) in specks {
+                data[(y * w + x) as usize] = 255;
+            }
+            for &(slot, code) in codes {
+                let t = template(code);
+                let (left, top) = (slots[slot].0 + 3, 4);
+                for ty in 0..t.height() {
+                    for tx in 0..t.width() {
+                        data[((top + ty) * w + left + tx) as usize] = t.pixel(tx, ty);
+                    }
+                }
+            }
+            GrayImage::new(w, h, data)
+        };
+
+        let img = draw(&[(0, 0xBD), (1, 0x1C)], &[]);
+        let buffer = ocr_buffer(&img, &slots, &templates, &OcrOptions::default()).unwrap();
+        assert_eq!(vec![Some(0xBD), Some(0x1C), None, None], buffer);
+
+        let img = draw(&[], &[]);
+        assert_eq!(vec![None; 4], ocr_buffer(&img, &slots, &templates, &OcrOptions::default()).unwrap());
+
+        let img = draw(&[(0, 0xBD), (2, 0x1C)], &[]);
+        let err = ocr_buffer(&img, &slots, &templates, &OcrOptions::default()).unwrap_err();
+        assert!(err.contains("slot 3"), "{}", err);
+
+        // bright pixels in empty slots are noise, not codes
+        let img = draw(&[(0, 0xBD)], &[(57, 15), (130, 2), (131, 2)]);
+        for method in [MatchMethod::Mismatch, MatchMethod::Ncc] {
+            let buffer = ocr_buffer(&img, &slots, &templates, &OcrOptions::new(method)).unwrap();
+            assert_eq!(vec![Some(0xBD), None, None, None], buffer, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn test_ocr_daemons() {
+        // pending, installed, pending shifted by 1 column and failed daemons
+        let templates = MatrixTemplates::load_templates();
+        let template = |code: u8| &templates.templates.iter().find(|(c, _)| *c == code).unwrap().1;
+        let (w, h) = (200u32, 140u32);
+        let mut data = vec![0u8; (w * h) as usize];
+        for (top, column, codes) in [(5, 0, vec![0x1C, 0x55, 0x7A]), (75, 1, vec![0xBD, 0xE9])] {
+            for (i, &code) in codes.iter().enumerate() {
+                let t = template(code);
+                let left = 5 + (column + i as u32) * 50;
+                for ty in 0..t.height() {
+                    for tx in 0..t.width() {
+                        data[((top + ty) * w + left + tx) as usize] = t.pixel(tx, ty);
+                    }
+                }
+            }
+        }
+        // banner text has the daemon color
+        fill(&mut data, w as usize, (20, 45, 120, 55));
+        let img = GrayImage::new(w, h, data);
+        let banner = |top: u32| {
+            let mut data = vec![0u8; (w * h) as usize];
+            fill(&mut data, w as usize, (0, top, w, top + 20));
+            GrayImage::new(w, h, data)
+        };
+
+        let daemons = ocr_daemons(&img, &banner(40), &banner(110), &templates, &OcrOptions::default()).unwrap();
+        let summary: Vec<(DaemonStatus, Vec<u8>, usize)> = daemons.iter()
+            .map(|d| (d.status, d.codes.iter().map(|c| c.code).collect(), d.offset))
+            .collect();
+        assert_eq!(vec![(5, 25), (40, 60), (75, 95), (110, 130)], daemons.iter().map(|d| (d.top, d.bottom)).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (DaemonStatus::Pending, vec![0x1C, 0x55, 0x7A], 0),
+            (DaemonStatus::Installed, vec![], 0),
+            (DaemonStatus::Pending, vec![0xBD, 0xE9], 1),
+            (DaemonStatus::Failed, vec![], 0),
+        ], summary);
+        assert!(daemons.iter().all(|d| d.uncertain.is_empty()));
+
+        // all daemons are finished
+        let empty = GrayImage::new(w, h, vec![0; (w * h) as usize]);
+        let daemons = ocr_daemons(&empty, &banner(40), &banner(110), &templates, &OcrOptions::default()).unwrap();
+        assert_eq!(2, daemons.len());
+        assert!(ocr_daemons(&empty, &empty, &empty, &templates, &OcrOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_find_banners_denoised() {
+        // scanline seam splits banner into thin halves, 1px line and specks are not banners
+        let (w, h) = (100u32, 60u32);
+        let mut data = vec![0u8; (w * h) as usize];
+        fill(&mut data, w as usize, (0, 5, w, 6));
+        fill(&mut data, w as usize, (0, 20, w, 27));
+        fill(&mut data, w as usize, (0, 28, w, 35));
+        for &(x, y) in [(10, 45), (50, 50), (51, 50), (90, 12)].iter() {
+            fill(&mut data, w as usize, (x, y, x + 1, y + 1));
+        }
+        let banners = find_banners(&GrayImage::new(w, h, data));
+        assert_eq!(vec![(20, 35)], banners.iter().map(|b| (b.start, b.end)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_load_pack() {
+        let dir = std::env::temp_dir().join("cyberbot2077_test_load_pack");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // embedded pack saved at 2x resolution
+        let embedded = MatrixTemplates::load_templates();
+        let mut manifest = "# code file scale\n".to_owned();
+        for (code, template) in embedded.templates.iter() {
+            // `#` inside a file name is not a comment
+            let file = format!("{:02X}#2x.bmp", code);
+            into_image(&template.scaled(2.0)).save(dir.join(&file)).unwrap();
+            manifest += &format!("{:02X} {} 0.5 # scaled 2x\n", code, file);
+        }
+        std::fs::write(dir.join(TEMPLATE_MANIFEST), manifest).unwrap();
+
+        for path in [dir.clone(), dir.join(TEMPLATE_MANIFEST)] {
+            let pack = MatrixTemplates::load_pack(&path).unwrap();
+            assert_eq!(embedded.templates.len(), pack.templates.len());
+            for ((code, expected), (actual_code, actual)) in embedded.templates.iter().zip(pack.templates.iter()) {
+                assert_eq!(code, actual_code);
+                assert_eq!(expected.width(), actual.width());
+                assert_eq!(expected.height(), actual.height());
+                for y in 0..expected.height() {
+                    for x in 0..expected.width() {
+                        assert_eq!(expected.pixel(x, y), actual.pixel(x, y));
+                    }
+                }
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_pack() {
+        let dir = std::env::temp_dir().join("cyberbot2077_test_save_pack");
+        let templates = MatrixTemplates::new(vec![
+            (0x1C, GrayImage::new(2, 2, vec![255, 0, 0, 255])),
+            (0xE9, GrayImage::new(3, 1, vec![0, 255, 0])),
+        ]);
+        templates.save_pack(&dir).unwrap();
+
+        let pack = MatrixTemplates::load_pack(&dir).unwrap();
+        assert_eq!(2, pack.templates.len());
+        for ((code, expected), (actual_code, actual)) in templates.templates.iter().zip(pack.templates.iter()) {
+            assert_eq!(code, actual_code);
+            assert_eq!(expected.width(), actual.width());
+            assert_eq!(expected.height(), actual.height());
+            for y in 0..expected.height() {
+                for x in 0..expected.width() {
+                    assert_eq!(expected.pixel(x, y), actual.pixel(x, y));
+                }
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_pack_errors() {
+        let dir = std::env::temp_dir().join("cyberbot2077_test_load_pack_errors");
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join(TEMPLATE_MANIFEST);
+
+        for content in ["", "# comment only\n", "1C\n", "XY 1c.bmp\n", "1C 1c.bmp -1\n", "1C missing.bmp\n"] {
+            std::fs::write(&manifest, content).unwrap();
+            assert!(MatrixTemplates::load_pack(&dir).is_err(), "{:?}", content);
+        }
+        assert!(MatrixTemplates::load_pack(dir.join("missing")).is_err());
+
+        // explicit pack never falls back to embedded one
+        assert!(MatrixTemplates::load_or_embedded(Some(&dir)).is_err());
+        assert_eq!(6, MatrixTemplates::load_or_embedded(None).unwrap().templates.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cell_confidence() {
+        let cell = |error, second_error| CellMatch { code: 0x1C, error, second_error };
+        assert_eq!(1.0, cell(0.0, 0.5).confidence());
+        assert_eq!(0.5, cell(0.25, 0.5).confidence());
+        assert_eq!(0.0, cell(0.5, 0.5).confidence());
+        assert_eq!(0.0, cell(0.0, 0.0).confidence());
+        assert_eq!(1.0, cell(0.3, f64::INFINITY).confidence());
+    }
+}
@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+// larger offsets may leave the smallest code glyph
+const MAX_JITTER: u32 = 8;
+const MAX_PATH_STEPS: u32 = 100;
+
+/// Click timings in milliseconds. Each delay is a random value of its inclusive `(min, max)` range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// After the cursor reaches a cell
+    pub before_press: (u64, u64),
+    /// Between button press and release
+    pub press: (u64, u64),
+    pub after_release: (u64, u64),
+    /// Max random offset of click point from cell target point in pixels
+    pub jitter: u32,
+    /// Count of intermediate cursor moves between cells. The cursor jumps to the cell if it is 0
+    pub path_steps: u32,
+    /// After each intermediate cursor move
+    pub step_delay: (u64, u64),
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            before_press: (300, 300),
+            press: (30, 30),
+            after_release: (200, 200),
+            jitter: 0,
+            path_steps: 0,
+            step_delay: (0, 0),
+        }
+    }
+}
+
+impl Pacing {
+    pub fn human() -> Self {
+        Self {
+            before_press: (150, 400),
+            press: (40, 110),
+            after_release: (120, 300),
+            jitter: 4,
+            path_steps: 12,
+            step_delay: (5, 15),
+        }
+    }
+
+    /// The longest click of a cell with the cursor path to it
+    pub fn max_step_duration(&self) -> Duration {
+        let path = self.path_steps as u64 * self.step_delay.1;
+        Duration::from_millis(path + self.before_press.1 + self.press.1 + self.after_release.1)
+    }
+
+    /// `default` or `human`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "human" => Some(Self::human()),
+            _ => None,
+        }
+    }
+
+    /// Profile name followed by comma separated overrides, e.g. `human,press=50-80,jitter=2`.
+    /// Keys are `before`, `press`, `after`, `step` with `<min>-<max>` or `<ms>` values and `jitter`, `path` with numbers
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let mut pacing = Self::by_name(name).ok_or_else(|| format!("Unknown pacing profile {}", name))?;
+        for part in parts {
+            let bad_part = || format!("Bad pacing option {}", part);
+            let (key, value) = part.split_once('=').ok_or_else(bad_part)?;
+            let range = || parse_range(value).ok_or_else(bad_part);
+            let number = || value.parse::<u32>().map_err(|_| bad_part());
+            match key {
+                "before" => pacing.before_press = range()?,
+                "press" => pacing.press = range()?,
+                "after" => pacing.after_release = range()?,
+                "step" => pacing.step_delay = range()?,
+                "jitter" => pacing.jitter = number()?,
+                "path" => pacing.path_steps = number()?,
+                _ => return Err(bad_part()),
+            }
+        }
+        if pacing.jitter > MAX_JITTER {
+            return Err(format!("Pacing jitter {} > {}", pacing.jitter, MAX_JITTER));
+        }
+        if pacing.path_steps > MAX_PATH_STEPS {
+            return Err(format!("Pacing path steps {} > {}", pacing.path_steps, MAX_PATH_STEPS));
+        }
+        return Ok(pacing);
+    }
+}
+
+/// `<min>-<max>` or `<ms>`
+fn parse_range(value: &str) -> Option<(u64, u64)> {
+    let (min, max) = match value.split_once('-') {
+        Some((min, max)) => (min.trim().parse().ok()?, max.trim().parse().ok()?),
+        None => {
+            let ms = value.trim().parse().ok()?;
+            (ms, ms)
+        }
+    };
+    return if min <= max { Some((min, max)) } else { None };
+}
+
+/// Xorshift generator. Humanized pacing needs no better randomness
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Zero seed is replaced, since the generator would return only zeros
+    pub fn new(seed: u64) -> Self {
+        Self { state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed } }
+    }
+
+    /// Seeded by current time
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        return x;
+    }
+
+    /// Value of inclusive `(min, max)` range
+    pub fn range(&mut self, (min, max): (u64, u64)) -> u64 {
+        if min >= max {
+            return min;
+        }
+        return min + self.next_u64() % (max - min + 1);
+    }
+
+    /// Value of `-radius..=radius`
+    pub fn offset(&mut self, radius: u32) -> i32 {
+        return self.range((0, 2 * radius as u64)) as i32 - radius as i32;
+    }
+
+    pub fn delay(&mut self, range: (u64, u64)) -> Duration {
+        Duration::from_millis(self.range(range))
+    }
+}
+
+/// Points from `from` (excluded) to `to` (included) with `steps` points between them.
+/// The cursor accelerates and slows down like a hand moved mouse
+pub fn interpolate(from: (i32, i32), to: (i32, i32), steps: u32) -> Vec<(i32, i32)> {
+    let count = steps + 1;
+    return (1..=count)
+        .map(|i| {
+            let t = i as f64 / count as f64;
+            let eased = t * t * (3.0 - 2.0 * t);
+            let lerp = |a: i32, b: i32| a + ((b - a) as f64 * eased).round() as i32;
+            (lerp(from.0, to.0), lerp(from.1, to.1))
+        })
+        .collect();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::pacing::{interpolate, Pacing, XorShift};
+
+    #[test]
+    fn test_parse_pacing() {
+        assert_eq!(Ok(Pacing::default()), Pacing::parse("default"));
+        assert_eq!(Ok(Pacing::human()), Pacing::parse("human"));
+
+        let pacing = Pacing::parse("default, press=50-80, before=100, jitter=2, path=5, step=1-3").unwrap();
+        assert_eq!((50, 80), pacing.press);
+        assert_eq!((100, 100), pacing.before_press);
+        assert_eq!((200, 200), pacing.after_release);
+        assert_eq!(2, pacing.jitter);
+        assert_eq!(5, pacing.path_steps);
+        assert_eq!((1, 3), pacing.step_delay);
+
+        assert!(Pacing::parse("slow").is_err());
+        assert!(Pacing::parse("default,press=80-50").is_err());
+        assert!(Pacing::parse("default,press").is_err());
+        assert!(Pacing::parse("default,speed=1").is_err());
+        assert!(Pacing::parse("default,jitter=20").is_err());
+    }
+
+    #[test]
+    fn test_max_step_duration() {
+        assert_eq!(Duration::from_millis(530), Pacing::default().max_step_duration());
+        assert_eq!(Duration::from_millis(400 + 110 + 300 + 12 * 15), Pacing::human().max_step_duration());
+    }
+
+    #[test]
+    fn test_xorshift_range() {
+        let mut rng = XorShift::new(0);
+        for _ in 0..1000 {
+            let v = rng.range((10, 20));
+            assert!((10..=20).contains(&v));
+            let o = rng.offset(3);
+            assert!((-3..=3).contains(&o));
+        }
+        assert_eq!(7, rng.range((7, 7)));
+
+        let (mut a, mut b) = (XorShift::new(42), XorShift::new(42));
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn test_interpolate() {
+        assert_eq!(vec![(10, 20)], interpolate((0, 0), (10, 20), 0));
+
+        let path = interpolate((0, 0), (100, -50), 4);
+        assert_eq!(5, path.len());
+        assert_eq!(Some(&(100, -50)), path.last());
+        // monotonic and slower at the ends
+        assert!(path.windows(2).all(|w| w[0].0 <= w[1].0 && w[0].1 >= w[1].1));
+        assert!(path[0].0 < path[2].0 - path[1].0);
+    }
+}
@@ -0,0 +1,251 @@
+use bmp::{Pixel, px};
+
+use crate::img::RgbImage;
+use crate::inflate::zlib_decompress;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+const GRAY: u8 = 0;
+const RGB: u8 = 2;
+const PALETTE: u8 = 3;
+const GRAY_ALPHA: u8 = 4;
+const RGBA: u8 = 6;
+
+/// Decodes non-interlaced 8 bits per channel PNG (gray, RGB, palette, with or without alpha).
+/// Alpha channel is ignored. Chunk CRCs are not verified
+pub fn decode(bytes: &[u8]) -> Result<RgbImage, String> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err("Not a PNG image".to_owned());
+    }
+
+    let mut header: Option<(u32, u32, u8)> = None;
+    let mut palette: &[u8] = &[];
+    let mut data: Vec<u8> = Vec::new();
+
+    let mut pos = SIGNATURE.len();
+    loop {
+        let len = read_u32(bytes, pos)? as usize;
+        let kind = bytes.get((pos + 4)..(pos + 8)).ok_or_else(|| "Unexpected end of PNG".to_owned())?;
+        let chunk = bytes.get((pos + 8)..(pos + 8 + len)).ok_or_else(|| "PNG chunk is truncated".to_owned())?;
+        pos += 12 + len;
+
+        match kind {
+            b"IHDR" => {
+                let (width, height) = (read_u32(chunk, 0)?, read_u32(chunk, 4)?);
+                let (depth, color_type, interlace) = match chunk.get(8..13) {
+                    Some(&[depth, color_type, _, _, interlace]) => (depth, color_type, interlace),
+                    _ => return Err("PNG header is truncated".to_owned()),
+                };
+                if depth != 8 {
+                    return Err(format!("Unsupported PNG bit depth {}", depth));
+                }
+                if interlace != 0 {
+                    return Err("Interlaced PNG is not supported".to_owned());
+                }
+                if width == 0 || height == 0 {
+                    return Err(format!("Bad image size {}x{}", width, height));
+                }
+                header = Some((width, height, color_type));
+            }
+            b"PLTE" => palette = chunk,
+            b"IDAT" => data.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {} // ancillary chunks
+        }
+    }
+
+    let (width, height, color_type) = header.ok_or_else(|| "PNG header was not found".to_owned())?;
+    let channels = match color_type {
+        GRAY | PALETTE => 1,
+        GRAY_ALPHA => 2,
+        RGB => 3,
+        RGBA => 4,
+        _ => return Err(format!("Unsupported PNG color type {}", color_type)),
+    };
+
+    let raw = zlib_decompress(&data)?;
+    let pixels = unfilter(&raw, width as usize, height as usize, channels)?;
+
+    let mut img = RgbImage::new(width, height);
+    for (i, p) in pixels.chunks_exact(channels).enumerate() {
+        let color = match color_type {
+            GRAY | GRAY_ALPHA => px!(p[0], p[0], p[0]),
+            PALETTE => {
+                let entry = palette.get((p[0] as usize * 3)..(p[0] as usize * 3 + 3))
+                    .ok_or_else(|| format!("Palette index {} is out of range", p[0]))?;
+                px!(entry[0], entry[1], entry[2])
+            }
+            _ => px!(p[0], p[1], p[2]),
+        };
+        img.set_pixel(i as u32 % width, i as u32 / width, color);
+    }
+    return Ok(img);
+}
+
+/// Reverts per-scanline filters. Returns `width * height * channels` bytes
+fn unfilter(raw: &[u8], width: usize, height: usize, channels: usize) -> Result<Vec<u8>, String> {
+    let stride = width.checked_mul(channels)
+        .ok_or_else(|| format!("{}x{} image is too large", width, height))?;
+    let raw_len = stride.checked_add(1).and_then(|n| n.checked_mul(height))
+        .ok_or_else(|| format!("{}x{} image is too large", width, height))?;
+    if raw.len() < raw_len {
+        return Err("PNG image data is truncated".to_owned());
+    }
+
+    let mut result = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[(y * (stride + 1) + 1)..((y + 1) * (stride + 1))];
+        let (prev, current) = result.split_at_mut(y * stride);
+        let prev = if y == 0 { None } else { Some(&prev[((y - 1) * stride)..]) };
+        let current = &mut current[..stride];
+
+        for i in 0..stride {
+            let a = if i >= channels { current[i - channels] } else { 0 };
+            let b = prev.map(|p| p[i]).unwrap_or(0);
+            let c = if i >= channels { prev.map(|p| p[i - channels]).unwrap_or(0) } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("Bad PNG filter type {}", filter)),
+            };
+            current[i] = line[i].wrapping_add(predictor);
+        }
+    }
+    return Ok(result);
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    return if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    };
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes.get(offset..(offset + 4))
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "Unexpected end of PNG".to_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bmp::{Pixel, px};
+
+    use crate::png::{decode, paeth, unfilter, SIGNATURE};
+
+    // each scanline uses different filter: none, sub, up, average, paeth, none...
+    fn expected_pixel(x: u32, y: u32) -> Pixel {
+        px!((x * 80 + y * 30) % 256, (x * 20 + y * 60 + 10) % 256, (255 - x * 40 - y * 10) % 256)
+    }
+
+    #[test]
+    fn test_decode_rgba() {
+        // 3x5 RGBA
+        let bytes = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x03,
+            0x00, 0x00, 0x00, 0x05, 0x08, 0x06, 0x00, 0x00, 0x00, 0x80, 0x71, 0x56, 0xA2, 0x00, 0x00, 0x00, 0x37, 0x49, 0x44, 0x41,
+            0x54, 0x78, 0xDA, 0x63, 0x60, 0xE0, 0xFA, 0x7F, 0x22, 0x40, 0xEE, 0xFA, 0xC9, 0x05, 0x46, 0xEB, 0x4F, 0x31, 0xCA, 0xB9,
+            0x7D, 0x3D, 0x19, 0x20, 0x72, 0x83, 0x11, 0x84, 0x99, 0xE4, 0x6C, 0xBE, 0x31, 0xC2, 0x30, 0xB3, 0x4D, 0x6D, 0x4E, 0x9A,
+            0xB9, 0xC6, 0x73, 0x46, 0x10, 0x66, 0x01, 0x8B, 0x8A, 0x40, 0x30, 0x00, 0x20, 0xFC, 0x16, 0x80, 0x94, 0x2E, 0x7F, 0xF1,
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let img = decode(&bytes).unwrap();
+        assert_eq!(3, img.width());
+        assert_eq!(5, img.height());
+        for y in 0..img.height() {
+            for x in 0..img.width() {
+                assert_eq!(expected_pixel(x, y), img.pixel(x, y), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_rgb() {
+        // 4x5 RGB
+        let bytes = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x04,
+            0x00, 0x00, 0x00, 0x05, 0x08, 0x02, 0x00, 0x00, 0x00, 0xED, 0xCF, 0xDA, 0x8C, 0x00, 0x00, 0x00, 0x38, 0x49, 0x44, 0x41,
+            0x54, 0x78, 0xDA, 0x63, 0x60, 0xE0, 0xFA, 0x1F, 0x20, 0x77, 0x7D, 0x81, 0xD1, 0xFA, 0x0F, 0x6E, 0xED, 0x8C, 0x72, 0x6E,
+            0x5F, 0x03, 0x44, 0x6E, 0x40, 0x10, 0x93, 0x9C, 0xCD, 0x37, 0x38, 0x62, 0xB6, 0xA9, 0xCD, 0x31, 0xD7, 0x78, 0x0E, 0x44,
+            0xDB, 0x35, 0x9E, 0xB3, 0x80, 0xC4, 0x44, 0x40, 0x28, 0x40, 0xE4, 0x1B, 0x00, 0x4F, 0x5D, 0x1A, 0x55, 0xFA, 0x9C, 0xB8,
+            0x29, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+        let img = decode(&bytes).unwrap();
+        assert_eq!(4, img.width());
+        assert_eq!(5, img.height());
+        for y in 0..img.height() {
+            for x in 0..img.width() {
+                assert_eq!(expected_pixel(x, y), img.pixel(x, y), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_palette() {
+        // 2x1 palette image, zlib stream with single stored block
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 8, 3, 0, 0, 0]));
+        bytes.extend(chunk(b"PLTE", &[10, 20, 30, 40, 50, 60]));
+        bytes.extend(chunk(b"IDAT", &[0x78, 0x01, 0x01, 0x03, 0x00, 0xFC, 0xFF, 0, 1, 0]));
+        bytes.extend(chunk(b"IEND", &[]));
+
+        let img = decode(&bytes).unwrap();
+        assert_eq!(px!(40, 50, 60), img.pixel(0, 0));
+        assert_eq!(px!(10, 20, 30), img.pixel(1, 0));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode(b"BM").is_err());
+        assert!(decode(&SIGNATURE).is_err());
+
+        // 16 bit depth
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 16, 2, 0, 0, 0]));
+        bytes.extend(chunk(b"IEND", &[]));
+        assert!(decode(&bytes).is_err());
+
+        // interlaced
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 1]));
+        bytes.extend(chunk(b"IEND", &[]));
+        assert!(decode(&bytes).is_err());
+
+        // no image data
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+        bytes.extend(chunk(b"IEND", &[]));
+        assert!(decode(&bytes).is_err());
+
+        // size overflows
+        assert!(unfilter(&[0; 16], usize::MAX / 2, 2, 4).is_err());
+        assert!(unfilter(&[0; 16], u32::MAX as usize, u32::MAX as usize, 4).is_err());
+    }
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(10, paeth(10, 20, 20));
+        assert_eq!(20, paeth(10, 20, 10));
+        assert_eq!(15, paeth(10, 20, 15));
+    }
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut c = (data.len() as u32).to_be_bytes().to_vec();
+        c.extend(kind);
+        c.extend(data);
+        c.extend([0u8; 4]); // CRC is not verified
+        return c;
+    }
+}
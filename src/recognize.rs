@@ -1,17 +1,165 @@
-use bmp::{Image, Pixel, px};
+use bmp::{Pixel, px};
 
-use crate::img::GrayImage;
+use crate::color::ColorFilter;
+use crate::debug::dump_mask;
+use crate::img::{GrayImage, RgbImage};
+use crate::morph::Component;
+use crate::ocr::{Daemon, MatchMethod, MatrixState, MatrixTemplates, ocr_buffer, ocr_daemons, ocr_matrix_state, OcrOptions};
 
 pub const MATRIX_COLOR: Pixel = px!(0xD0, 0xED, 0x57);
 
-const CONDITION_BORDER_COLOR: Pixel = px!(0x81, 0x96, 0x38);
+pub const CONDITION_BORDER_COLOR: Pixel = px!(0x81, 0x96, 0x38);
 pub const CONDITION_COLOR: Pixel = px!(0xF0, 0xF0, 0xF0);
 
-const BUFFER_COLOR: Pixel = px!(0x4F, 0x5A, 0x25);
+pub const BUFFER_COLOR: Pixel = px!(0x4F, 0x5A, 0x25);
+
+// background of active matrix row/column
+const HIGHLIGHT_COLOR: Pixel = px!(0x2B, 0x31, 0x16);
+
+// banners over finished daemons
+const INSTALLED_COLOR: Pixel = px!(0x1E, 0xE0, 0x8C);
+const FAILED_COLOR: Pixel = px!(0xFF, 0x5C, 0x5C);
+
+// pulsing highlight and screen gamma dim colors by up to 25% and brighten them by up to 7%.
+// Lab presets are centered in this range
+const LAB_CENTER_BRIGHTNESS: f32 = 0.9;
+
+/// Color filter of each recognition stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageFilters {
+    pub matrix: ColorFilter,
+    pub condition_border: ColorFilter,
+    pub condition: ColorFilter,
+    pub buffer: ColorFilter,
+    pub highlight: ColorFilter,
+    pub installed: ColorFilter,
+    pub failed: ColorFilter,
+}
+
+impl Default for StageFilters {
+    fn default() -> Self {
+        Self::rgb()
+    }
+}
+
+impl StageFilters {
+    /// `±threshold` box around each channel of reference colors
+    pub fn rgb() -> Self {
+        Self {
+            matrix: ColorFilter::Rgb { color: MATRIX_COLOR, threshold: 50 },
+            condition_border: ColorFilter::Rgb { color: CONDITION_BORDER_COLOR, threshold: 30 },
+            condition: ColorFilter::Rgb { color: CONDITION_COLOR, threshold: 50 },
+            buffer: ColorFilter::Rgb { color: BUFFER_COLOR, threshold: 30 },
+            highlight: ColorFilter::Rgb { color: HIGHLIGHT_COLOR, threshold: 12 },
+            installed: ColorFilter::Rgb { color: INSTALLED_COLOR, threshold: 40 },
+            failed: ColorFilter::Rgb { color: FAILED_COLOR, threshold: 40 },
+        }
+    }
+
+    /// Hue/saturation ranges. Matrix, daemon border, buffer and highlight have the same hue (~72°),
+    /// so they are separated by value
+    pub fn hsv() -> Self {
+        Self {
+            matrix: ColorFilter::Hsv { hue: (60.0, 85.0), saturation: (0.4, 1.0), value: (0.66, 1.0) },
+            condition_border: ColorFilter::Hsv { hue: (60.0, 85.0), saturation: (0.4, 1.0), value: (0.41, 0.66) },
+            condition: ColorFilter::Hsv { hue: (0.0, 360.0), saturation: (0.0, 0.15), value: (0.65, 1.0) },
+            buffer: ColorFilter::Hsv { hue: (60.0, 85.0), saturation: (0.35, 1.0), value: (0.23, 0.41) },
+            highlight: ColorFilter::Hsv { hue: (60.0, 85.0), saturation: (0.35, 1.0), value: (0.12, 0.23) },
+            installed: ColorFilter::Hsv { hue: (140.0, 170.0), saturation: (0.5, 1.0), value: (0.6, 1.0) },
+            failed: ColorFilter::Hsv { hue: (345.0, 15.0), saturation: (0.4, 1.0), value: (0.6, 1.0) },
+        }
+    }
+
+    /// Perceptual distance to reference colors dimmed by `LAB_CENTER_BRIGHTNESS`, since dimmed colors
+    /// lose chroma too. Lightness difference is half-weighted
+    pub fn lab() -> Self {
+        let lab = |color: Pixel, distance: f32| {
+            let c = |v: u8| (v as f32 * LAB_CENTER_BRIGHTNESS).round() as u8;
+            ColorFilter::Lab { color: px!(c(color.r), c(color.g), c(color.b)), distance, lightness_weight: 0.5 }
+        };
+        Self {
+            matrix: lab(MATRIX_COLOR, 15.0),
+            condition_border: lab(CONDITION_BORDER_COLOR, 11.0),
+            condition: lab(CONDITION_COLOR, 20.0),
+            buffer: lab(BUFFER_COLOR, 8.0),
+            highlight: lab(HIGHLIGHT_COLOR, 6.0),
+            installed: lab(INSTALLED_COLOR, 20.0),
+            failed: lab(FAILED_COLOR, 20.0),
+        }
+    }
+
+    /// `rgb`, `hsv` or `lab`
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(Self::rgb()),
+            "hsv" => Some(Self::hsv()),
+            "lab" => Some(Self::lab()),
+            _ => None,
+        }
+    }
+
+    /// Comma separated preset name and `<stage>=<preset>` overrides, e.g. `hsv,condition=lab` or `matrix=lab`.
+    /// Stages without override use the preset, `rgb` by default
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut result = Self::default();
+        for (i, part) in spec.split(',').map(str::trim).enumerate() {
+            let unknown_preset = |name: &str| format!("Unknown filter {}", name);
+            match part.split_once('=') {
+                None if i == 0 => result = Self::by_name(part).ok_or_else(|| unknown_preset(part))?,
+                None => return Err(format!("Filter preset {} must be the first", part)),
+                Some((stage, name)) => {
+                    let preset = Self::by_name(name.trim()).ok_or_else(|| unknown_preset(name))?;
+                    let stage = stage.trim();
+                    let filter = preset.stage(stage).ok_or_else(|| format!("Unknown filter stage {}", stage))?;
+                    *result.stage_mut(stage).unwrap() = *filter;
+                }
+            }
+        }
+        return Ok(result);
+    }
+
+    /// Filter by field name
+    fn stage(&self, name: &str) -> Option<&ColorFilter> {
+        match name {
+            "matrix" => Some(&self.matrix),
+            "condition_border" => Some(&self.condition_border),
+            "condition" => Some(&self.condition),
+            "buffer" => Some(&self.buffer),
+            "highlight" => Some(&self.highlight),
+            "installed" => Some(&self.installed),
+            "failed" => Some(&self.failed),
+            _ => None,
+        }
+    }
 
-pub fn find_matrix_area(img: &Image) -> Option<(u32, u32, u32, u32)> {
+    fn stage_mut(&mut self, name: &str) -> Option<&mut ColorFilter> {
+        match name {
+            "matrix" => Some(&mut self.matrix),
+            "condition_border" => Some(&mut self.condition_border),
+            "condition" => Some(&mut self.condition),
+            "buffer" => Some(&mut self.buffer),
+            "highlight" => Some(&mut self.highlight),
+            "installed" => Some(&mut self.installed),
+            "failed" => Some(&mut self.failed),
+            _ => None,
+        }
+    }
+}
+
+pub fn find_matrix_area(img: &RgbImage, filters: &StageFilters) -> Option<(u32, u32, u32, u32)> {
     // matrix is on left part of image
-    let img = GrayImage::filter(img, &MATRIX_COLOR, 50, 0, 0, img.get_width() / 2, img.get_height());
+    locate_matrix(img, img.width() / 2, filters)
+}
+
+/// Searches the whole width, e.g. access point places matrix on the right
+fn find_any_matrix_area(img: &RgbImage, filters: &StageFilters) -> Option<(u32, u32, u32, u32)> {
+    find_matrix_area(img, filters).or_else(|| locate_matrix(img, img.width(), filters))
+}
+
+fn locate_matrix(img: &RgbImage, search_width: u32, filters: &StageFilters) -> Option<(u32, u32, u32, u32)> {
+    let area = (0, 0, search_width, img.height());
+    let img = GrayImage::filter_by(img, &filters.matrix, area.0, area.1, area.2, area.3);
+    dump_mask("matrix_search", &img, &area, &filters.matrix);
 
     // ██████████████████
     // █ matrix caption █
@@ -31,16 +179,9 @@ pub fn find_matrix_area(img: &Image) -> Option<(u32, u32, u32, u32)> {
     // ███████████████│
     // ───────────────┤  <- (x_right, y_top) is here
     // matrix content │
-    debug_assert_eq!(img.pixel(x_right - 2, y_top + 0), 255);
-    debug_assert_eq!(img.pixel(x_right - 1, y_top + 0), 255);
-    debug_assert_eq!(img.pixel(x_right + 0, y_top + 0), 255);
-    debug_assert_eq!(img.pixel(x_right + 1, y_top + 0), 0);
-    debug_assert_eq!(img.pixel(x_right + 2, y_top + 0), 0);
-    debug_assert_eq!(img.pixel(x_right - 2, y_top + 1), 0);
-    debug_assert_eq!(img.pixel(x_right - 1, y_top + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 0, y_top + 1), 255);
-    debug_assert_eq!(img.pixel(x_right + 1, y_top + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 2, y_top + 1), 0);
+    if !matches_shape(&img, x_right, y_top, &["███··", "··█··"]) {
+        return None;
+    }
 
     let y_bottom = match (100..(img.height() - y_top - 1)).find(|&dy| img.pixel(x_right, y_top + dy + 1) == 0) {
         Some(height) => y_top + height,
@@ -49,16 +190,9 @@ pub fn find_matrix_area(img: &Image) -> Option<(u32, u32, u32, u32)> {
 
     // matrix content │
     // ───────────────┘ <- (x_right, y_bottom) is here
-    debug_assert_eq!(img.pixel(x_right - 2, y_bottom + 0), 255);
-    debug_assert_eq!(img.pixel(x_right - 1, y_bottom + 0), 255);
-    debug_assert_eq!(img.pixel(x_right + 0, y_bottom + 0), 255);
-    debug_assert_eq!(img.pixel(x_right + 1, y_bottom + 0), 0);
-    debug_assert_eq!(img.pixel(x_right + 2, y_bottom + 0), 0);
-    debug_assert_eq!(img.pixel(x_right - 2, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right - 1, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 0, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 1, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 2, y_bottom + 1), 0);
+    if !matches_shape(&img, x_right, y_bottom, &["███··", "·····"]) {
+        return None;
+    }
 
     let x_left = match (300..=x_right).find(|&dx| img.pixel(x_right - dx, y_bottom - 1) != 0) {
         Some(width) => x_right - width,
@@ -67,16 +201,129 @@ pub fn find_matrix_area(img: &Image) -> Option<(u32, u32, u32, u32)> {
     return Some((x_left + 1, y_top + 1, x_right - 1, y_bottom - 1));
 }
 
-pub fn find_condition_area(img: &Image, matrix_area: &(u32, u32, u32, u32)) -> Option<(u32, u32, u32, u32)> {
+/// Checks mask rows starting at `y`, each row is 5 px centered at `x`. `█` is a set pixel, `·` is an empty one.
+/// Pixels outside of `img` are empty
+fn matches_shape(img: &GrayImage, x: u32, y: u32, shape: &[&str]) -> bool {
+    shape.iter().enumerate().all(|(dy, row)| {
+        row.chars().enumerate().all(|(dx, c)| {
+            let set = match ((x + dx as u32).checked_sub(2), y + dy as u32) {
+                (Some(x), y) if x < img.width() && y < img.height() => img.pixel(x, y) != 0,
+                _ => false,
+            };
+            set == (c == '█')
+        })
+    })
+}
+
+/// Timer digits are the highest text line above matrix caption.
+/// Caption and timer frame are wider than half of matrix, so they are skipped
+pub fn find_timer_area(img: &RgbImage, matrix_area: &(u32, u32, u32, u32), filters: &StageFilters) -> Option<(u32, u32, u32, u32)> {
+    let (matrix_left, matrix_top, matrix_right, _) = *matrix_area;
+    let area = (matrix_left, matrix_top / 2, matrix_right, matrix_top);
+    if area.2 <= area.0 || area.3 <= area.1 {
+        return None;
+    }
+    let img = GrayImage::filter_by(img, &filters.matrix, area.0, area.1, area.2, area.3);
+    dump_mask("timer_search", &img, &area, &filters.matrix);
+
+    // ┌──────────────────┐
+    // │ BREACH TIME      │
+    // │ 14.56            │
+    // └──────────────────┘
+    // ██████████████████████
+    // █   matrix caption   █
+
+    let glyphs: Vec<Component> = img.components().into_iter()
+        .filter(|c| c.width() * 2 < img.width())
+        .collect();
+    let mut lines: Vec<(u32, u32)> = glyphs.iter().map(|c| (c.top, c.bottom + 1)).collect();
+    lines.sort_unstable();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for (top, bottom) in lines {
+        match merged.last_mut() {
+            Some(line) if top <= line.1 => line.1 = line.1.max(bottom),
+            _ => merged.push((top, bottom)),
+        }
+    }
+    let (top, bottom) = merged.into_iter().max_by_key(|(top, bottom)| bottom - top)?;
+    let line: Vec<&Component> = glyphs.iter().filter(|c| top <= c.top && c.bottom < bottom).collect();
+    let left = line.iter().map(|c| c.left).min()?;
+    let right = line.iter().map(|c| c.right).max()?;
+    return Some((area.0 + left, area.1 + top, area.0 + right + 1, area.1 + bottom));
+}
+
+fn locate_conditions(img: &RgbImage, matrix_area: &(u32, u32, u32, u32), filters: &StageFilters) -> Option<((u32, u32, u32, u32), Option<(u32, u32, u32, u32)>)> {
     // conditions are near matrix
     let (_, matrix_top, matrix_right, matrix_bottom) = *matrix_area;
 
-    let img = GrayImage::filter(img, &CONDITION_BORDER_COLOR, 30, matrix_right, matrix_top, img.get_width(), matrix_bottom);
+    let area = (matrix_right, matrix_top, img.width(), matrix_bottom);
+    let span = GrayImage::filter_by(img, &filters.condition_border, area.0, area.1, area.2, area.3);
+    dump_mask("daemon_border", &span, &area, &filters.condition_border);
 
     // │ condition content    descriptions │
     // └───────────────────────────────────┘
 
-    // tries to find the most bottom-right horizontal line
+    // many daemons make the panel longer than matrix, so its border is followed below
+    let max_gap = (matrix_bottom - matrix_top) / 2;
+    let reach = img.height().min(matrix_bottom + max_gap);
+    let (x_left, x_right, y_bottom) = match find_panel_bottom(&span) {
+        Some(bottom) => bottom,
+        None => find_panel_bottom(&GrayImage::filter_by(img, &filters.condition_border, area.0, area.1, area.2, reach))?,
+    };
+    let area = (matrix_right, matrix_top, img.width(), img.height());
+    let img = GrayImage::filter_by(img, &filters.condition_border, area.0, area.1, area.2, area.3);
+    let y_bottom = follow_panel_border(&img, x_left, x_right, y_bottom, max_gap);
+
+    // │ condition content    descriptions │
+    // └───────────────────────────────────┘ <- (x_right, y_bottom) is here
+    // ^
+    // (x_left, y_bottom) is here
+    // need to filter condition descriptions. Searching for description images
+
+    let desc_start_x = img.rect_hull(x_left + 1, 0, x_right - 1, y_bottom - 1)
+        .map(|(desc_start_x, _, _, _)| desc_start_x);
+    let width = desc_start_x.unwrap_or(x_right) - x_left;
+
+    let condition_area = (matrix_right + x_left + 1, matrix_top, matrix_right + width - 1, matrix_top + y_bottom - 1);
+    let description_area = desc_start_x
+        .map(|desc_start_x| (matrix_right + desc_start_x, matrix_top, matrix_right + x_right, matrix_top + y_bottom - 1));
+    return Some((condition_area, description_area));
+}
+
+/// Searches the whole screenshot, if daemon panel is not next to matrix (tutorial, access point).
+/// Panel top is its top border line, since matrix span can't be used
+fn locate_any_conditions(img: &RgbImage, matrix_area: &(u32, u32, u32, u32), filters: &StageFilters) -> Option<((u32, u32, u32, u32), Option<(u32, u32, u32, u32)>)> {
+    if let Some(r) = locate_conditions(img, matrix_area, filters) {
+        return Some(r);
+    }
+    let area = (0, 0, img.width(), img.height());
+    let img = GrayImage::filter_by(img, &filters.condition_border, area.0, area.1, area.2, area.3);
+    dump_mask("daemon_border_search", &img, &area, &filters.condition_border);
+
+    // ┌───────────────────────────────────┐ <- the nearest line above, which is half filled
+    // │ condition content    descriptions │
+    // └───────────────────────────────────┘
+
+    let (x_left, x_right, y_bottom) = find_panel_bottom(&img)?;
+    let half = (x_right - x_left) / 2;
+    let y_top = (0..y_bottom).rev()
+        .find(|&y| img.count(x_left + 1, y, x_right + 1, y + 1) >= half)
+        .unwrap_or(0);
+    if y_bottom - y_top < 3 {
+        return None;
+    }
+
+    let desc_start_x = img.rect_hull(x_left + 1, y_top + 1, x_right - 1, y_bottom - 1)
+        .map(|(desc_start_x, _, _, _)| desc_start_x);
+    let condition_area = (x_left + 1, y_top + 1, desc_start_x.unwrap_or(x_right) - 1, y_bottom - 1);
+    let description_area = desc_start_x
+        .map(|desc_start_x| (desc_start_x, y_top + 1, x_right, y_bottom - 1));
+    return Some((condition_area, description_area));
+}
+
+/// Returns `(x_left, x_right, y_bottom)` of the most bottom-right horizontal line.
+/// `x_left` is the last empty pixel before the line
+fn find_panel_bottom(img: &GrayImage) -> Option<(u32, u32, u32)> {
     let rect_width = 300;
     let rect_height = 1;
     let (x_right, y_bottom) = match img.rfind_rect(rect_width, rect_height) {
@@ -86,39 +333,40 @@ pub fn find_condition_area(img: &Image, matrix_area: &(u32, u32, u32, u32)) -> O
 
     // │ condition content    descriptions │
     // └───────────────────────────────────┘ <- (x_right, y_bottom) is here
-    debug_assert_eq!(img.pixel(x_right - 2, y_bottom + 0), 255);
-    debug_assert_eq!(img.pixel(x_right - 1, y_bottom + 0), 255);
-    debug_assert_eq!(img.pixel(x_right + 0, y_bottom + 0), 255);
-    debug_assert_eq!(img.pixel(x_right + 1, y_bottom + 0), 0);
-    debug_assert_eq!(img.pixel(x_right + 2, y_bottom + 0), 0);
-    debug_assert_eq!(img.pixel(x_right - 2, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right - 1, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 0, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 1, y_bottom + 1), 0);
-    debug_assert_eq!(img.pixel(x_right + 2, y_bottom + 1), 0);
+    if !matches_shape(&img, x_right, y_bottom, &["███··", "·····"]) {
+        return None;
+    }
 
     let x_left = match (300..x_right).find(|dx| img.pixel(x_right - dx - 1, y_bottom) == 0) {
         Some(width) => x_right - width,
         None => return None,
     };
+    return Some((x_left, x_right, y_bottom));
+}
 
-    // │ condition content    descriptions │
-    // └───────────────────────────────────┘ <- (x_right, y_bottom) is here
-    // ^
-    // (x_left, y_bottom) is here
-    // need to filter condition descriptions. Searching for description images
-
-    let width = img.rect_hull(x_left + 1, 0, x_right - 1, y_bottom - 1)
-        .map(|(desc_start_x, _, _, _)| desc_start_x)
-        .unwrap_or(x_right) - x_left;
-
-    return Some((matrix_right + x_left + 1, matrix_top, matrix_right + width - 1, matrix_top + y_bottom - 1));
+/// Moves `y_bottom` down to the last line of the same span, which is at most `max_gap` below the previous one
+fn follow_panel_border(img: &GrayImage, x_left: u32, x_right: u32, y_bottom: u32, max_gap: u32) -> u32 {
+    let mut y_bottom = y_bottom;
+    let width = x_right - x_left;
+    while let Some(y) = (y_bottom + 1..img.height().min(y_bottom + max_gap + 1))
+        .rev()
+        .find(|&y| img.count(x_left + 1, y, x_right + 1, y + 1) == width) {
+        y_bottom = y;
+    }
+    return y_bottom;
 }
 
-pub fn find_buffer_size(img: &Image, condition_area: &(u32, u32, u32, u32)) -> Option<usize> {
+fn locate_buffer(img: &RgbImage, condition_area: &(u32, u32, u32, u32), filters: &StageFilters) -> Option<((u32, u32, u32, u32), usize, Vec<(u32, u32)>)> {
     let (condition_left, condition_top, condition_right, _) = *condition_area;
+    locate_buffer_in(img, (condition_left, condition_top / 2, condition_right, 3 * condition_top / 4), filters)
+}
 
-    let img = GrayImage::filter(img, &BUFFER_COLOR, 30, condition_left, condition_top / 2, condition_right, 3 * condition_top / 4);
+fn locate_buffer_in(img: &RgbImage, area: (u32, u32, u32, u32), filters: &StageFilters) -> Option<((u32, u32, u32, u32), usize, Vec<(u32, u32)>)> {
+    if area.2 <= area.0 || area.3 <= area.1 {
+        return None;
+    }
+    let img = GrayImage::filter_by(img, &filters.buffer, area.0, area.1, area.2, area.3);
+    dump_mask("buffer", &img, &area, &filters.buffer);
 
     // ───────────────────┐
     //  ┌ ─ ┐ ┌ ─ ┐ ┌ ─ ┐ │
@@ -142,23 +390,103 @@ pub fn find_buffer_size(img: &Image, condition_area: &(u32, u32, u32, u32)) -> O
     };
 
     let y = y_bottom - height / 2;
-    debug_assert_eq!(img.pixel(x_right + 2, y), 0);
-    debug_assert_eq!(img.pixel(x_right + 1, y), 0);
-    debug_assert_eq!(img.pixel(x_right + 0, y), 255);
-    debug_assert_eq!(img.pixel(x_right - 1, y), 0);
-    debug_assert_eq!(img.pixel(x_right - 2, y), 0);
+    if !matches_shape(&img, x_right, y, &["··█··"]) {
+        return None;
+    }
 
     let count = (0..x_right).filter(|&x| img.pixel(x, y) != 0).count() / 2;
-    return Some(count);
+    let x_left = (0..x_right).find(|&x| img.pixel(x, y) != 0).unwrap_or(x_right);
+
+    // slot is between a pair of neighbour border runs
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for x in (0..x_right).filter(|&x| img.pixel(x, y) != 0) {
+        match runs.last_mut() {
+            Some(run) if run.1 + 1 == x => run.1 = x,
+            _ => runs.push((x, x)),
+        }
+    }
+    let slots = runs.chunks_exact(2)
+        .map(|pair| (pair[0].1 + 1 - x_left, pair[1].0 - 1 - x_left))
+        .collect();
+    let area = (
+        area.0 + x_left,
+        area.1 + y_bottom - height,
+        area.0 + x_right,
+        area.1 + y_bottom,
+    );
+    return Some((area, count, slots));
+}
+
+/// Buffer box found by `find_layout`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buffer {
+    /// First slot's left border ... right border of buffer box
+    pub area: (u32, u32, u32, u32),
+    pub size: usize,
+    /// Inner `(left, right)` bounds (inclusive) of each slot relative to `area`. Slots span the whole area height
+    pub slots: Vec<(u32, u32)>,
+}
+
+/// Panels of breach screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub matrix: (u32, u32, u32, u32),
+    pub conditions: (u32, u32, u32, u32),
+    pub descriptions: Option<(u32, u32, u32, u32)>,
+    pub buffer: Option<Buffer>,
+}
+
+/// Finds each panel independently. The usual places (matrix on the left, daemons to the right of it,
+/// buffer above daemons) are tried first, then the whole screenshot is searched
+pub fn find_layout(img: &RgbImage, filters: &StageFilters) -> Result<Layout, String> {
+    let matrix = find_any_matrix_area(img, filters).ok_or_else(|| "Matrix was not found".to_owned())?;
+    let (conditions, descriptions) = locate_any_conditions(img, &matrix, filters).ok_or_else(|| "Conditions were not found".to_owned())?;
+    let buffer = locate_buffer(img, &conditions, filters)
+        .or_else(|| locate_buffer_in(img, (0, 0, img.width(), img.height()), filters))
+        .map(|(area, size, slots)| Buffer { area, size, slots });
+    return Ok(Layout { matrix, conditions, descriptions, buffer });
+}
+
+/// Recognizes matrix at `area` of `find_layout`. Grayscale is matched only by `MatchMethod::Ncc`
+pub fn read_matrix(img: &RgbImage, area: &(u32, u32, u32, u32), filters: &StageFilters, templates: &MatrixTemplates, options: &OcrOptions) -> Result<MatrixState, String> {
+    let matrix_img = GrayImage::filter_by(img, &filters.matrix, area.0, area.1, area.2, area.3);
+    dump_mask("matrix", &matrix_img, area, &filters.matrix);
+    let luma_img = match options.method {
+        MatchMethod::Ncc => Some(GrayImage::luma(img, area.0, area.1, area.2, area.3)),
+        MatchMethod::Mismatch => None,
+    };
+    let highlight_img = GrayImage::filter_by(img, &filters.highlight, area.0, area.1, area.2, area.3);
+    dump_mask("highlight", &highlight_img, area, &filters.highlight);
+    return ocr_matrix_state(&matrix_img, &highlight_img, luma_img.as_ref(), templates, options);
+}
+
+/// Recognizes daemons at `area` of `find_layout` with their status
+pub fn read_daemons(img: &RgbImage, area: &(u32, u32, u32, u32), filters: &StageFilters, templates: &MatrixTemplates, options: &OcrOptions) -> Result<Vec<Daemon>, String> {
+    let condition_img = GrayImage::filter_by(img, &filters.condition, area.0, area.1, area.2, area.3);
+    dump_mask("daemon", &condition_img, area, &filters.condition);
+    let installed_img = GrayImage::filter_by(img, &filters.installed, area.0, area.1, area.2, area.3);
+    dump_mask("daemon_installed", &installed_img, area, &filters.installed);
+    let failed_img = GrayImage::filter_by(img, &filters.failed, area.0, area.1, area.2, area.3);
+    dump_mask("daemon_failed", &failed_img, area, &filters.failed);
+    return ocr_daemons(&condition_img, &installed_img, &failed_img, templates, options);
 }
 
+/// Recognizes code of each `buffer` slot. `None` means empty slot
+pub fn read_buffer(img: &RgbImage, buffer: &Buffer, filters: &StageFilters, templates: &MatrixTemplates, options: &OcrOptions) -> Result<Vec<Option<u8>>, String> {
+    let area = buffer.area;
+    let buffer_img = GrayImage::filter_by(img, &filters.matrix, area.0, area.1, area.2, area.3);
+    dump_mask("buffer_codes", &buffer_img, &area, &filters.matrix);
+    return ocr_buffer(&buffer_img, &buffer.slots, templates, options);
+}
 
 #[cfg(test)]
 mod tests {
-    use bmp::Image;
+    use bmp::{Pixel, px};
 
-    use crate::img::load_img_from_file;
-    use crate::recognize::{find_buffer_size, find_condition_area, find_matrix_area};
+    use crate::img::{GrayImage, load_img_from_file, RgbImage};
+    use crate::recognize::{BUFFER_COLOR, CONDITION_BORDER_COLOR, CONDITION_COLOR, FAILED_COLOR, find_layout, find_matrix_area, find_timer_area, locate_buffer, locate_conditions, HIGHLIGHT_COLOR, INSTALLED_COLOR, locate_matrix, matches_shape, MATRIX_COLOR, read_buffer, read_daemons, read_matrix, StageFilters};
+    use crate::ocr::{codes, MatrixTemplates, OcrOptions};
+    use crate::test_layouts::{render, render_daemons, VARIANTS};
     use crate::test_cases::{BUFFER_SIZE1, BUFFER_SIZE2, BUFFER_SIZE3, BUFFER_SIZE4, BUFFER_SIZE5, CONDITION_AREA1, CONDITION_AREA2, CONDITION_AREA3, CONDITION_AREA4, CONDITION_AREA5, FILE1, FILE2, FILE3, FILE4, FILE5, MATRIX_AREA1, MATRIX_AREA2, MATRIX_AREA3, MATRIX_AREA4, MATRIX_AREA5};
 
     #[test]
@@ -187,91 +515,334 @@ mod tests {
     }
 
     fn test_find_matrix_area(filename: &str, expected: (u32, u32, u32, u32)) {
-        let img = load_img_from_file(filename);
-        let actual = find_matrix_area(&img);
+        let img = load_img_from_file(filename).unwrap();
+        let actual = find_matrix_area(&img, &StageFilters::default());
         assert_eq!(Some(expected), actual);
     }
 
     #[test]
     fn test_find_matrix_area_not_found() {
-        let img = Image::new(2, 2);
-        let area = find_matrix_area(&img);
+        let img = RgbImage::new(2, 2);
+        let area = find_matrix_area(&img, &StageFilters::default());
         assert_eq!(None, area);
     }
 
+    #[test]
+    fn test_find_matrix_area_at_right_edge() {
+        // caption and borders touch the right edge of the searched area
+        let mut img = RgbImage::new(400, 300);
+        let mut fill = |(left, top, right, bottom): (u32, u32, u32, u32)| {
+            for y in top..bottom {
+                for x in left..right {
+                    img.set_pixel(x, y, MATRIX_COLOR);
+                }
+            }
+        };
+        fill((50, 10, 400, 20));
+        fill((50, 20, 51, 250));
+        fill((399, 20, 400, 250));
+        fill((50, 249, 400, 250));
+        assert_eq!(Some((51, 20, 398, 248)), locate_matrix(&img, 400, &StageFilters::default()));
+    }
+
+    #[test]
+    fn test_matches_shape() {
+        // 3x2 mask with the top row set
+        let img = GrayImage::new(3, 2, vec![255, 255, 255, 0, 0, 0]);
+        assert!(matches_shape(&img, 2, 0, &["███··", "·····"]));
+        assert!(matches_shape(&img, 0, 1, &["·····", "·····"]));
+        assert!(!matches_shape(&img, 2, 0, &["███··", "··█··"]));
+        assert!(!matches_shape(&img, 2, 1, &["··█··"]));
+    }
+
     #[test]
     fn test_find_condition_area1() {
-        test_find_condition_area(FILE1, &MATRIX_AREA1, CONDITION_AREA1);
+        test_find_condition_area(FILE1, CONDITION_AREA1);
     }
 
     #[test]
     fn test_find_condition_area2() {
-        test_find_condition_area(FILE2, &MATRIX_AREA2, CONDITION_AREA2);
+        test_find_condition_area(FILE2, CONDITION_AREA2);
     }
 
     #[test]
     fn test_find_condition_area3() {
-        test_find_condition_area(FILE3, &MATRIX_AREA3, CONDITION_AREA3);
+        test_find_condition_area(FILE3, CONDITION_AREA3);
     }
 
     #[test]
     fn test_find_condition_area4() {
-        test_find_condition_area(FILE4, &MATRIX_AREA4, CONDITION_AREA4);
+        test_find_condition_area(FILE4, CONDITION_AREA4);
     }
 
     #[test]
     fn test_find_condition_area5() {
-        test_find_condition_area(FILE5, &MATRIX_AREA5, CONDITION_AREA5);
+        test_find_condition_area(FILE5, CONDITION_AREA5);
     }
 
-    fn test_find_condition_area(filename: &str, matrix_area: &(u32, u32, u32, u32), expected: (u32, u32, u32, u32)) {
-        let img = load_img_from_file(filename);
-        let actual = find_condition_area(&img, matrix_area);
-        assert_eq!(Some(expected), actual);
+    fn test_find_condition_area(filename: &str, expected: (u32, u32, u32, u32)) {
+        let img = load_img_from_file(filename).unwrap();
+        let layout = find_layout(&img, &StageFilters::default()).unwrap();
+        assert_eq!(expected, layout.conditions);
     }
 
     #[test]
     fn test_find_condition_area_not_found() {
-        let img = Image::new(2, 2);
-        let area = find_condition_area(&img, &(0, 0, 1, 1));
+        let img = RgbImage::new(2, 2);
+        let area = locate_conditions(&img, &(0, 0, 1, 1), &StageFilters::default());
         assert_eq!(None, area);
     }
 
     #[test]
     fn test_find_buffer_size1() {
-        test_find_buffer_size(FILE1, &CONDITION_AREA1, BUFFER_SIZE1);
+        test_find_buffer_size(FILE1, BUFFER_SIZE1);
     }
 
     #[test]
     fn test_find_buffer_size2() {
-        test_find_buffer_size(FILE2, &CONDITION_AREA2, BUFFER_SIZE2);
+        test_find_buffer_size(FILE2, BUFFER_SIZE2);
     }
 
     #[test]
     fn test_find_buffer_size3() {
-        test_find_buffer_size(FILE3, &CONDITION_AREA3, BUFFER_SIZE3);
+        test_find_buffer_size(FILE3, BUFFER_SIZE3);
     }
 
     #[test]
     fn test_find_buffer_size4() {
-        test_find_buffer_size(FILE4, &CONDITION_AREA4, BUFFER_SIZE4);
+        test_find_buffer_size(FILE4, BUFFER_SIZE4);
     }
 
     #[test]
     fn test_find_buffer_size5() {
-        test_find_buffer_size(FILE5, &CONDITION_AREA5, BUFFER_SIZE5);
+        test_find_buffer_size(FILE5, BUFFER_SIZE5);
     }
 
-    fn test_find_buffer_size(filename: &str, condition_area: &(u32, u32, u32, u32), expected: usize) {
-        let img = load_img_from_file(filename);
-        let actual = find_buffer_size(&img, condition_area);
-        assert_eq!(Some(expected), actual);
+    fn test_find_buffer_size(filename: &str, expected: usize) {
+        let img = load_img_from_file(filename).unwrap();
+        let layout = find_layout(&img, &StageFilters::default()).unwrap();
+        assert_eq!(Some(expected), layout.buffer.map(|b| b.size));
+    }
+
+    #[test]
+    fn test_find_layout_variants() {
+        let filters = StageFilters::default();
+        let templates = MatrixTemplates::load_templates();
+        for variant in VARIANTS {
+            let fixture = render(variant);
+            let layout = find_layout(&fixture.img, &filters).unwrap_or_else(|e| panic!("{:?}: {}", variant, e));
+            assert_eq!(fixture.matrix_area, layout.matrix, "{:?}", variant);
+            assert_eq!(Some(fixture.buffer_size), layout.buffer.as_ref().map(|b| b.size), "{:?}", variant);
+            assert!(layout.descriptions.is_some(), "{:?}", variant);
+
+            let matrix = read_matrix(&fixture.img, &layout.matrix, &filters, &templates, &OcrOptions::default()).unwrap();
+            assert_eq!(fixture.matrix, codes(&matrix.code_cells().unwrap()), "{:?}", variant);
+
+            let daemons = read_daemons(&fixture.img, &layout.conditions, &filters, &templates, &OcrOptions::default()).unwrap();
+            let actual: Vec<Vec<u8>> = daemons.iter().map(|d| d.codes.iter().map(|c| c.code).collect()).collect();
+            assert_eq!(fixture.conditions, actual, "{:?}", variant);
+
+            let buffer = read_buffer(&fixture.img, &layout.buffer.unwrap(), &filters, &templates, &OcrOptions::default()).unwrap();
+            assert_eq!(vec![None; fixture.buffer_size], buffer, "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn test_find_layout_4_daemons() {
+        test_find_layout_daemons(
+            vec![vec![0x1C, 0x55], vec![0xBD, 0xE9, 0xFF], vec![0x7A, 0x1C, 0x1C, 0xFF], vec![0x55, 0x55]],
+            vec![0, 1, 0, 2],
+        );
     }
 
     #[test]
-    fn test_find_buffer_size_not_found() {
-        let img = Image::new(6, 6);
-        let count = find_buffer_size(&img, &(4, 4, 6, 6));
-        assert_eq!(None, count);
+    fn test_find_layout_5_daemons() {
+        test_find_layout_daemons(
+            vec![vec![0xE9, 0x1C], vec![0x1C, 0x55, 0xBD], vec![0xBD, 0xE9, 0xFF], vec![0x7A, 0x1C, 0x1C, 0xFF], vec![0xFF, 0x55, 0x7A]],
+            vec![1, 0, 2, 0, 1],
+        );
     }
-}
\ No newline at end of file
+
+    fn test_find_layout_daemons(conditions: Vec<Vec<u8>>, offsets: Vec<usize>) {
+        let filters = StageFilters::default();
+        let fixture = render_daemons(conditions, offsets);
+        let layout = find_layout(&fixture.img, &filters).unwrap();
+        assert_eq!(fixture.matrix_area, layout.matrix);
+        // panel ends below matrix, but is still found next to it
+        assert!(layout.conditions.3 > layout.matrix.3);
+        assert_eq!(Some(layout.conditions), locate_conditions(&fixture.img, &layout.matrix, &filters).map(|(area, _)| area));
+
+        let daemons = read_daemons(&fixture.img, &layout.conditions, &filters, &MatrixTemplates::load_templates(), &OcrOptions::default()).unwrap();
+        let actual: Vec<Vec<u8>> = daemons.iter().map(|d| d.codes.iter().map(|c| c.code).collect()).collect();
+        assert_eq!(fixture.conditions, actual);
+        assert_eq!(fixture.offsets, daemons.iter().map(|d| d.offset).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_find_timer_area() {
+        // caption, timer frame with small label and timer digits
+        let mut img = RgbImage::new(400, 400);
+        let mut fill = |(left, top, right, bottom): (u32, u32, u32, u32)| {
+            for y in top..bottom {
+                for x in left..right {
+                    img.set_pixel(x, y, MATRIX_COLOR);
+                }
+            }
+        };
+        fill((20, 180, 380, 200));
+        fill((20, 105, 220, 106));
+        fill((20, 165, 220, 166));
+        fill((20, 105, 21, 166));
+        fill((219, 105, 220, 166));
+        for i in 0..6 {
+            fill((30 + i * 8, 112, 35 + i * 8, 117));
+        }
+        for i in 0..4 {
+            fill((30 + i * 20, 130, 42 + i * 20, 155));
+        }
+        let filters = StageFilters::default();
+        assert_eq!(Some((30, 130, 102, 155)), find_timer_area(&img, &(20, 200, 380, 380), &filters));
+        assert_eq!(None, find_timer_area(&RgbImage::new(400, 400), &(20, 200, 380, 380), &filters));
+    }
+
+    #[test]
+    fn test_find_description_area_not_found() {
+        let img = RgbImage::new(6, 6);
+        let area = locate_conditions(&img, &(0, 0, 3, 6), &StageFilters::default()).and_then(|(_, area)| area);
+        assert_eq!(None, area);
+    }
+
+    #[test]
+    fn test_find_buffer_not_found() {
+        let img = RgbImage::new(6, 6);
+        let buffer = locate_buffer(&img, &(4, 4, 6, 6), &StageFilters::default());
+        assert_eq!(None, buffer);
+    }
+
+    #[test]
+    fn test_find_buffer_slots() {
+        // frame right border and 3 slots of 30 px between `condition_top / 2` and `3 * condition_top / 4`
+        let mut img = RgbImage::new(300, 400);
+        for y in 105..145 {
+            img.set_pixel(250, y, BUFFER_COLOR);
+        }
+        for x in [10, 40, 50, 80, 90, 120] {
+            for y in 110..140 {
+                img.set_pixel(x, y, BUFFER_COLOR);
+            }
+        }
+        let filters = StageFilters::default();
+        let expected = ((10, 105, 250, 144), 3, vec![(1, 29), (41, 69), (81, 109)]);
+        assert_eq!(Some(expected), locate_buffer(&img, &(0, 200, 300, 400), &filters));
+    }
+
+    #[test]
+    fn test_stage_filters_match_reference_colors() {
+        for name in ["rgb", "hsv", "lab"] {
+            let filters = StageFilters::by_name(name).unwrap();
+            assert!(filters.matrix.matcher()(0xD0, 0xED, 0x57), "{}", name);
+            assert!(filters.condition_border.matcher()(0x81, 0x96, 0x38), "{}", name);
+            assert!(filters.condition.matcher()(0xF0, 0xF0, 0xF0), "{}", name);
+            assert!(filters.buffer.matcher()(0x4F, 0x5A, 0x25), "{}", name);
+            assert!(filters.highlight.matcher()(0x2B, 0x31, 0x16), "{}", name);
+            assert!(filters.installed.matcher()(0x1E, 0xE0, 0x8C), "{}", name);
+            assert!(filters.failed.matcher()(0xFF, 0x5C, 0x5C), "{}", name);
+
+            // stages do not catch each other's colors
+            assert!(!filters.matrix.matcher()(0x81, 0x96, 0x38), "{}", name);
+            assert!(!filters.condition_border.matcher()(0x4F, 0x5A, 0x25), "{}", name);
+            assert!(!filters.buffer.matcher()(0xD0, 0xED, 0x57), "{}", name);
+            assert!(!filters.highlight.matcher()(0x4F, 0x5A, 0x25), "{}", name);
+            assert!(!filters.buffer.matcher()(0x2B, 0x31, 0x16), "{}", name);
+            assert!(!filters.highlight.matcher()(0, 0, 0), "{}", name);
+            assert!(!filters.installed.matcher()(0xD0, 0xED, 0x57), "{}", name);
+            assert!(!filters.failed.matcher()(0xF0, 0xF0, 0xF0), "{}", name);
+            assert!(!filters.condition.matcher()(0xFF, 0x5C, 0x5C), "{}", name);
+        }
+        assert_eq!(None, StageFilters::by_name("cmyk"));
+    }
+
+    fn shift_brightness(p: Pixel, k: f32) -> Pixel {
+        let c = |v: u8| (v as f32 * k).round().min(255.0) as u8;
+        px!(c(p.r), c(p.g), c(p.b))
+    }
+
+    #[test]
+    fn test_stage_filters_match_brightness_shifted_colors() {
+        // pulsing highlight and screen gamma shift brightness by -25%..+7%
+        let stages = [
+            ("matrix", MATRIX_COLOR),
+            ("condition_border", CONDITION_BORDER_COLOR),
+            ("condition", CONDITION_COLOR),
+            ("buffer", BUFFER_COLOR),
+            ("highlight", HIGHLIGHT_COLOR),
+            ("installed", INSTALLED_COLOR),
+            ("failed", FAILED_COLOR),
+        ];
+        for name in ["hsv", "lab"] {
+            let filters = StageFilters::by_name(name).unwrap();
+            for (stage, color) in stages {
+                let matcher = filters.stage(stage).unwrap().matcher();
+                for k in [0.75, 0.85, 1.0, 1.07] {
+                    let p = shift_brightness(color, k);
+                    assert!(matcher(p.r, p.g, p.b), "{} {} {}", name, stage, k);
+                }
+                // the nearest colors of other stages, e.g. darker matrix and brighter daemon border
+                for (other, other_color) in stages.iter().filter(|(other, _)| *other != stage) {
+                    for k in [0.75, 1.07] {
+                        let p = shift_brightness(*other_color, k);
+                        assert!(!matcher(p.r, p.g, p.b), "{} {} catches {} {}", name, stage, other, k);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_layout_brightness_shifted() {
+        let templates = MatrixTemplates::load_templates();
+        for variant in VARIANTS {
+            let fixture = render(variant);
+            for k in [0.75, 1.07] {
+                let mut img = fixture.img.clone();
+                for y in 0..img.height() {
+                    for x in 0..img.width() {
+                        img.set_pixel(x, y, shift_brightness(img.pixel(x, y), k));
+                    }
+                }
+                for name in ["hsv", "lab"] {
+                    let filters = StageFilters::by_name(name).unwrap();
+                    let layout = find_layout(&img, &filters).unwrap_or_else(|e| panic!("{:?} {} {}: {}", variant, k, name, e));
+                    assert_eq!(fixture.matrix_area, layout.matrix, "{:?} {} {}", variant, k, name);
+                    assert_eq!(Some(fixture.buffer_size), layout.buffer.map(|b| b.size), "{:?} {} {}", variant, k, name);
+
+                    let matrix = read_matrix(&img, &layout.matrix, &filters, &templates, &OcrOptions::default()).unwrap();
+                    assert_eq!(fixture.matrix, codes(&matrix.code_cells().unwrap()), "{:?} {} {}", variant, k, name);
+                    let daemons = read_daemons(&img, &layout.conditions, &filters, &templates, &OcrOptions::default()).unwrap();
+                    let actual: Vec<Vec<u8>> = daemons.iter().map(|d| d.codes.iter().map(|c| c.code).collect()).collect();
+                    assert_eq!(fixture.conditions, actual, "{:?} {} {}", variant, k, name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_stage_filters() {
+        assert_eq!(Ok(StageFilters::hsv()), StageFilters::parse("hsv"));
+
+        let filters = StageFilters::parse("hsv, condition=lab, buffer=rgb").unwrap();
+        assert_eq!(StageFilters::hsv().matrix, filters.matrix);
+        assert_eq!(StageFilters::lab().condition, filters.condition);
+        assert_eq!(StageFilters::rgb().buffer, filters.buffer);
+
+        // rgb is the base without preset
+        let filters = StageFilters::parse("matrix=lab").unwrap();
+        assert_eq!(StageFilters::lab().matrix, filters.matrix);
+        assert_eq!(StageFilters::rgb().highlight, filters.highlight);
+
+        assert!(StageFilters::parse("cmyk").is_err());
+        assert!(StageFilters::parse("matrix=cmyk").is_err());
+        assert!(StageFilters::parse("timer=hsv").is_err());
+        assert!(StageFilters::parse("matrix=hsv,lab").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::img::RgbImage;
+use crate::recognize::StageFilters;
+
+// every n-th pixel of every n-th row is counted in palette histogram
+const SAMPLE_STEP: u32 = 4;
+// the shortest run of matrix color, which is a caption bar, like `find_matrix_area` searches
+const CAPTION_MIN_WIDTH: u32 = 300;
+// smaller shares of the reference colors are noise of unrelated screens
+const MIN_MATRIX_SHARE: f64 = 0.001;
+const MIN_HIGHLIGHT_SHARE: f64 = 0.002;
+const MIN_BANNER_SHARE: f64 = 0.002;
+
+/// What the screenshot shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    NotBreach,
+    /// Nothing is selected yet
+    BreachIdle,
+    /// Some cells are selected or daemons are already installed or failed, the active line is still shown.
+    /// Palette sees only finished daemons, see `Screen::with_progress`
+    BreachInProgress,
+    /// Active line is gone and daemons are covered with banners
+    BreachFinished,
+}
+
+impl Screen {
+    /// Idle breach with recognized used matrix cells or filled buffer slots is in progress
+    pub fn with_progress(self, used_cells: usize, filled_slots: usize) -> Screen {
+        return match self {
+            Screen::BreachIdle if used_cells > 0 || filled_slots > 0 => Screen::BreachInProgress,
+            screen => screen,
+        };
+    }
+}
+
+/// Shares of sampled pixels matching stage filters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub matrix: f64,
+    pub highlight: f64,
+    /// Installed and failed daemon banners
+    pub banners: f64,
+    /// Matrix caption bar is found
+    pub caption: bool,
+}
+
+impl Palette {
+    pub fn of(img: &RgbImage, filters: &StageFilters) -> Self {
+        let matrix = filters.matrix.matcher();
+        let highlight = filters.highlight.matcher();
+        let installed = filters.installed.matcher();
+        let failed = filters.failed.matcher();
+
+        let (mut total, mut matrix_count, mut highlight_count, mut banner_count) = (0u32, 0u32, 0u32, 0u32);
+        for y in (0..img.height()).step_by(SAMPLE_STEP as usize) {
+            for x in (0..img.width()).step_by(SAMPLE_STEP as usize) {
+                let p = img.pixel(x, y);
+                total += 1;
+                if matrix(p.r, p.g, p.b) {
+                    matrix_count += 1;
+                } else if highlight(p.r, p.g, p.b) {
+                    highlight_count += 1;
+                } else if installed(p.r, p.g, p.b) || failed(p.r, p.g, p.b) {
+                    banner_count += 1;
+                }
+            }
+        }
+        let share = |count: u32| if total == 0 { 0.0 } else { count as f64 / total as f64 };
+
+        // caption is at least 5 px high, so every second row is enough
+        let caption = (0..img.height()).step_by(2).any(|y| {
+            let mut run = 0;
+            (0..img.width()).any(|x| {
+                let p = img.pixel(x, y);
+                run = if matrix(p.r, p.g, p.b) { run + 1 } else { 0 };
+                run >= CAPTION_MIN_WIDTH
+            })
+        });
+
+        Self {
+            matrix: share(matrix_count),
+            highlight: share(highlight_count),
+            banners: share(banner_count),
+            caption,
+        }
+    }
+
+    /// Finished breach needs both banners and no active line, so a shifted highlight color
+    /// does not stop an idle breach
+    pub fn classify(&self) -> Screen {
+        if !self.caption || self.matrix < MIN_MATRIX_SHARE {
+            return Screen::NotBreach;
+        }
+        let banners = self.banners >= MIN_BANNER_SHARE;
+        let highlight = self.highlight >= MIN_HIGHLIGHT_SHARE;
+        return match (banners, highlight) {
+            (false, _) => Screen::BreachIdle,
+            (true, true) => Screen::BreachInProgress,
+            (true, false) => Screen::BreachFinished,
+        };
+    }
+}
+
+/// Cheap check before full recognition
+pub fn classify_screen(img: &RgbImage, filters: &StageFilters) -> Screen {
+    Palette::of(img, filters).classify()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bmp::{Pixel, px};
+
+    use crate::img::RgbImage;
+    use crate::recognize::{MATRIX_COLOR, StageFilters};
+    use crate::screen::{classify_screen, Palette, Screen};
+    use crate::test_layouts::{render, VARIANTS};
+
+    fn fill(img: &mut RgbImage, (left, top, right, bottom): (u32, u32, u32, u32), color: Pixel) {
+        for y in top..bottom {
+            for x in left..right {
+                img.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    #[test]
+    fn test_classify_screen() {
+        let filters = StageFilters::default();
+        assert_eq!(Screen::NotBreach, classify_screen(&RgbImage::new(800, 600), &filters));
+
+        // matrix color without caption bar, e.g. yellow UI of another screen
+        let mut img = RgbImage::new(800, 600);
+        fill(&mut img, (10, 10, 200, 100), MATRIX_COLOR);
+        assert_eq!(Screen::NotBreach, classify_screen(&img, &filters));
+
+        // caption bar and highlighted line
+        let mut img = RgbImage::new(800, 600);
+        fill(&mut img, (20, 100, 380, 120), MATRIX_COLOR);
+        fill(&mut img, (20, 130, 380, 170), px!(0x2B, 0x31, 0x16));
+        assert_eq!(Screen::BreachIdle, classify_screen(&img, &filters));
+
+        // installed daemon
+        fill(&mut img, (420, 130, 700, 160), px!(0x1E, 0xE0, 0x8C));
+        assert_eq!(Screen::BreachInProgress, classify_screen(&img, &filters));
+
+        // active line is gone
+        fill(&mut img, (20, 130, 380, 170), px!(0, 0, 0));
+        fill(&mut img, (420, 170, 700, 200), px!(0xFF, 0x5C, 0x5C));
+        assert_eq!(Screen::BreachFinished, classify_screen(&img, &filters));
+    }
+
+    #[test]
+    fn test_with_progress() {
+        assert_eq!(Screen::BreachIdle, Screen::BreachIdle.with_progress(0, 0));
+        assert_eq!(Screen::BreachInProgress, Screen::BreachIdle.with_progress(2, 0));
+        assert_eq!(Screen::BreachInProgress, Screen::BreachIdle.with_progress(0, 1));
+        assert_eq!(Screen::BreachInProgress, Screen::BreachInProgress.with_progress(0, 0));
+        assert_eq!(Screen::BreachFinished, Screen::BreachFinished.with_progress(3, 3));
+        assert_eq!(Screen::NotBreach, Screen::NotBreach.with_progress(1, 1));
+    }
+
+    #[test]
+    fn test_classify_layout_variants() {
+        for variant in VARIANTS {
+            assert_eq!(Screen::BreachIdle, classify_screen(&render(variant).img, &StageFilters::default()), "{:?}", variant);
+        }
+    }
+
+    #[test]
+    fn test_caption_must_be_long() {
+        let mut img = RgbImage::new(800, 600);
+        fill(&mut img, (20, 100, 300, 120), MATRIX_COLOR);
+        let palette = Palette::of(&img, &StageFilters::default());
+        assert!(!palette.caption);
+        assert!(palette.matrix > 0.0);
+
+        // access point matrix is in the right half
+        let mut img = RgbImage::new(800, 600);
+        fill(&mut img, (420, 100, 780, 120), MATRIX_COLOR);
+        assert_eq!(Screen::BreachIdle, classify_screen(&img, &StageFilters::default()));
+    }
+}
@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::vec;
+use crate::ocr::ActiveLine;
 use crate::util::{is_part_of, new_vec, union_point};
 
 #[derive(Debug, Clone)]
@@ -59,6 +60,14 @@ pub fn filter_best(all_solutions: &Vec<Solution>) -> Vec<Solution> {
     return result;
 }
 
+/// The best of `filter_best` solutions with at most `step_limit` steps, e.g. to finish before the breach timer.
+/// The shortest solution if none fits
+pub fn best_within(best: &[Solution], step_limit: usize) -> Option<&Solution> {
+    return best.iter().rev()
+        .find(|s| s.steps.len() <= step_limit)
+        .or_else(|| best.iter().min_by_key(|s| s.steps.len()));
+}
+
 /// Find all unique solutions. Use `filter_best` to filter and sort them.
 pub fn solve(matrix: &Vec<Vec<u8>>, conditions: &Vec<Vec<u8>>, step_limit: usize) -> Vec<Solution> {
     // Each solution covers single condition. Not finalized.
@@ -80,6 +89,60 @@ pub fn solve(matrix: &Vec<Vec<u8>>, conditions: &Vec<Vec<u8>>, step_limit: usize
     }).collect();
 }
 
+/// Continues breach in progress. Use `filter_best` to filter and sort solutions.
+/// - `matrix` cells are `None` if they are used;
+/// - the first step is in `line`, then directions alternate;
+/// - `buffer` holds the committed codes. Conditions may start in it;
+/// - solutions contain only new steps, `step_limit` is count of free buffer slots
+pub fn solve_from(matrix: &Vec<Vec<Option<u8>>>, line: ActiveLine, buffer: &[u8], conditions: &Vec<Vec<u8>>, step_limit: usize) -> Vec<Solution> {
+    debug_assert!(conditions.len() <= 64);
+    let mut result = Vec::new();
+    let mut codes = Vec::from(buffer);
+    let mut steps = Vec::with_capacity(step_limit);
+    extend_path(matrix, line, conditions, step_limit, 0, &mut codes, &mut steps, &mut result);
+    return result;
+}
+
+/// Tries each free cell of `line` as the next step. Path is saved each time it meets one more condition.
+/// `met` is bit mask of conditions met by `steps`
+fn extend_path(
+    matrix: &Vec<Vec<Option<u8>>>, line: ActiveLine, conditions: &Vec<Vec<u8>>, step_limit: usize, met: u64,
+    codes: &mut Vec<u8>, steps: &mut Vec<Step>, result: &mut Vec<Solution>,
+) {
+    if steps.len() >= step_limit {
+        return;
+    }
+    let cells: Vec<(usize, usize)> = match line {
+        ActiveLine::Row(y) => (0..matrix[y].len()).map(|x| (x, y)).collect(),
+        ActiveLine::Column(x) => (0..matrix.len()).map(|y| (x, y)).collect(),
+    };
+    for (x, y) in cells {
+        let step = Step::new(x as u8, y as u8);
+        let code = match matrix[y][x] {
+            Some(code) if !steps.contains(&step) => code,
+            _ => continue,
+        };
+        steps.push(step);
+        codes.push(code);
+
+        let now_met = conditions.iter().enumerate()
+            .filter(|(_, c)| codes.ends_with(c))
+            .fold(met, |mask, (i, _)| mask | 1 << i);
+        if now_met != met {
+            let conds = (0..conditions.len()).map(|i| now_met & 1 << i != 0).collect();
+            result.push(Solution { steps: steps.clone(), conditions: conds });
+        }
+        let next_line = match line {
+            ActiveLine::Row(_) => ActiveLine::Column(x),
+            ActiveLine::Column(_) => ActiveLine::Row(y),
+        };
+        extend_path(matrix, next_line, conditions, step_limit, now_met, codes, steps, result);
+
+        steps.pop();
+        codes.pop();
+    }
+}
+
 /// Merges solutions.
 /// - if `b` solution is small piece of `a` solution - corrects `a` conditions. `a` solution covers `a+b` conditions;
 /// - if `b` solution's start is ending of `a` solution OR `a` and `b` have no same steps - tries
@@ -336,7 +399,8 @@ fn is_horizontal_step(s1: &Step, s2: &Step) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use crate::solver::{finalize_solution, find_condition_solutions, is_horizontal_step, next_possible_steps, next_step_is_horizontal, Step};
+    use crate::ocr::ActiveLine;
+    use crate::solver::{best_within, filter_best, finalize_solution, find_condition_solutions, is_horizontal_step, next_possible_steps, next_step_is_horizontal, Solution, solve_from, Step};
 
     #[test]
     fn test_is_horizontal_step() {
@@ -450,4 +514,39 @@ mod tests {
         let no_solutions = find_condition_solutions(&vec![0, 1, 8], None, &matrix);
         assert_eq!(Vec::<Vec<Step>>::new(), no_solutions);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_best_within() {
+        let solution = |steps: usize, conditions: Vec<bool>| Solution { steps: vec![Step::new(0, 0); steps], conditions };
+        let best = vec![solution(2, vec![true, false]), solution(4, vec![false, true]), solution(6, vec![true, true])];
+        assert_eq!(6, best_within(&best, 8).unwrap().steps.len());
+        assert_eq!(4, best_within(&best, 5).unwrap().steps.len());
+        assert_eq!(2, best_within(&best, 1).unwrap().steps.len());
+        assert!(best_within(&[], 8).is_none());
+    }
+
+    #[test]
+    fn test_solve_from() {
+        // (1, 0) and (1, 2) are used, buffer is [7, 9] and column 1 is active
+        let matrix = vec![
+            vec![Some(1), None, Some(2)],
+            vec![Some(3), Some(4), Some(5)],
+            vec![Some(4), None, Some(6)],
+        ];
+        let conditions = vec![vec![9, 4], vec![4, 3], vec![5, 6], vec![1, 2]];
+        let solutions = solve_from(&matrix, ActiveLine::Column(1), &[7, 9], &conditions, 3);
+        let best = filter_best(&solutions);
+        let summary: Vec<(Vec<bool>, Vec<Step>)> = best.iter().map(|s| (s.conditions.clone(), s.steps.clone())).collect();
+        assert_eq!(vec![
+            // the first condition starts in the buffer
+            (vec![true, false, false, false], vec![Step::new(1, 1)]),
+            (vec![true, true, false, false], vec![Step::new(1, 1), Step::new(0, 1)]),
+            (vec![true, false, true, false], vec![Step::new(1, 1), Step::new(2, 1), Step::new(2, 2)]),
+        ], summary);
+
+        // the only free cell of the line is already in the path
+        let solutions = solve_from(&matrix, ActiveLine::Column(1), &[], &conditions, 3);
+        assert!(solutions.iter().all(|s| s.steps[0] == Step::new(1, 1)));
+        assert!(solve_from(&matrix, ActiveLine::Row(0), &[], &conditions, 0).is_empty());
+    }
+}
@@ -1,7 +1,13 @@
-use crate::{ocr, recognize, solver};
-use crate::img::{GrayImage, load_img_from_file};
-use crate::recognize::{CONDITION_COLOR, MATRIX_COLOR};
+use crate::{execute, ocr, recognize, solver};
+use crate::img::{load_img_from_file};
+use crate::input::{Clicker, PointerAction, RecordingBackend};
+use crate::monitor::{Desktop, ScreenMapping};
+use crate::ocr::{MatrixTemplates, OcrOptions};
+use crate::pacing::{Pacing, XorShift};
+use crate::recognize::StageFilters;
 use crate::solver::{Solution, Step};
+use crate::test_layouts::{render, Variant};
+use crate::text::TextGlyphs;
 
 pub const FILE1: &str = "test/test1.bmp";
 
@@ -423,33 +429,86 @@ fn test9() {
     test(FILE9, &matrix9(), &conditions9(), BUFFER_SIZE9, &solutions9(), HAS_FULL_SOLUTION9);
 }
 
+#[test]
+fn test_execute_clicks() {
+    let fixture = render(Variant::Standard);
+    let mut input = RecordingBackend::default();
+    let mut clicker = Clicker::new(&mut input, None, Pacing::default(), XorShift::new(1));
+    execute(fixture.img.clone(), &MatrixTemplates::load_templates(), &TextGlyphs::load_font(), &TextGlyphs::load_timer_font(), &StageFilters::default(), &OcrOptions::default(), Some(&mut clicker), None).unwrap();
+
+    // cursor is reset to the corner, then moved relatively
+    let moves: Vec<(i32, i32)> = input.actions.iter()
+        .filter_map(|a| match *a {
+            PointerAction::MoveRelative(dx, dy) => Some((dx, dy)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(Some(&(-5000, -5000)), moves.first());
+    let clicked: Vec<(usize, usize)> = moves[1..].iter()
+        .scan((0, 0), |cur, &(dx, dy)| {
+            *cur = (cur.0 + dx, cur.1 + dy);
+            Some(fixture.cell_at((cur.0 as u32, cur.1 as u32)).expect("click misses matrix codes"))
+        })
+        .collect();
+
+    let solutions = solver::solve(&fixture.matrix, &fixture.conditions, fixture.buffer_size);
+    let best = solver::filter_best(&solutions);
+    let expected: Vec<(usize, usize)> = best.last().unwrap().steps.iter().map(|s| (s.x as usize, s.y as usize)).collect();
+    assert_eq!(expected, clicked);
+    assert_eq!(moves.len(), input.actions.iter().filter(|&&a| a == PointerAction::Press).count());
+}
+
+#[test]
+fn test_execute_clicks_absolute() {
+    // fullscreen game on 200% scaled primary monitor with another monitor to the left
+    let desktop = Desktop { virtual_screen: (-1000, 0, 1640, 360), primary: (640, 360), dpi: 192 };
+    let fixture = render(Variant::Standard);
+    let mut input = RecordingBackend::default();
+    let mut clicker = Clicker::new(&mut input, ScreenMapping::new(fixture.img.width(), fixture.img.height(), &desktop), Pacing::default(), XorShift::new(1));
+    execute(fixture.img.clone(), &MatrixTemplates::load_templates(), &TextGlyphs::load_font(), &TextGlyphs::load_timer_font(), &StageFilters::default(), &OcrOptions::default(), Some(&mut clicker), None).unwrap();
+
+    assert!(!input.actions.iter().any(|a| matches!(a, PointerAction::MoveRelative(_, _))));
+    let clicked: Vec<(usize, usize)> = input.actions.iter()
+        .filter_map(|a| match *a {
+            PointerAction::MoveAbsolute(x, y) => Some(fixture.cell_at((x as u32 * 2, y as u32 * 2)).expect("click misses matrix codes")),
+            _ => None,
+        })
+        .collect();
+
+    let solutions = solver::solve(&fixture.matrix, &fixture.conditions, fixture.buffer_size);
+    let best = solver::filter_best(&solutions);
+    let expected: Vec<(usize, usize)> = best.last().unwrap().steps.iter().map(|s| (s.x as usize, s.y as usize)).collect();
+    assert_eq!(expected, clicked);
+}
+
 fn test(path: &str, expected_matrix: &Vec<Vec<u8>>, expected_conditions: &Vec<Vec<u8>>, expected_steps: usize, expected_solutions: &Vec<Solution>, has_full_solution: bool) {
     let templates = ocr::MatrixTemplates::load_templates();
 
-    let img = load_img_from_file(path);
+    let img = load_img_from_file(path).unwrap();
+    let filters = StageFilters::default();
 
-    let matrix_area = recognize::find_matrix_area(&img).expect("Matrix was not found");
-    let matrix_img = GrayImage::filter(&img, &MATRIX_COLOR, 50, matrix_area.0, matrix_area.1, matrix_area.2, matrix_area.3);
-    let matrix = match ocr::ocr_matrix(&matrix_img, &templates) {
+    let layout = match recognize::find_layout(&img, &filters) {
         Ok(r) => r,
+        Err(err) => panic!("Layout was not found: {}", err),
+    };
+    let options = ocr::OcrOptions::default();
+
+    let matrix = match recognize::read_matrix(&img, &layout.matrix, &filters, &templates, &options) {
+        Ok(r) => ocr::codes(&r.code_cells().expect("Matrix has used cells")),
         Err(err) => panic!("Matrix was not recognized: {}", err),
     };
-    drop(matrix_img);
-    assert_eq!(*expected_matrix, matrix.4);
+    assert_eq!(*expected_matrix, matrix);
 
-    let condition_area = recognize::find_condition_area(&img, &matrix_area).expect("Conditions were not found");
-    let condition_img = GrayImage::filter(&img, &CONDITION_COLOR, 50, condition_area.0, condition_area.1, condition_area.2, condition_area.3);
-    let conditions = match ocr::ocr_conditions(&condition_img, &templates) {
-        Ok(r) => r,
+    let conditions = match recognize::read_daemons(&img, &layout.conditions, &filters, &templates, &options) {
+        Ok(r) => r.iter().map(|d| d.codes.iter().map(|c| c.code).collect()).collect::<Vec<Vec<u8>>>(),
         Err(err) => panic!("Conditions were not recognized: {}", err),
     };
-    drop(condition_img);
     assert_eq!(*expected_conditions, conditions);
 
-    let steps = recognize::find_buffer_size(&img, &condition_area).expect("Buffer size was not recognized");
+    let steps = layout.buffer.expect("Buffer was not found").size;
     assert_eq!(expected_steps, steps);
 
-    let solutions = solver::solve(&matrix.4, &conditions, steps);
+    let solutions = solver::solve(&matrix, &conditions, steps);
     for expected in expected_solutions.iter() {
         let found = solutions.iter().any(|actual| actual.conditions == expected.conditions && actual.steps == expected.steps);
         assert!(found);
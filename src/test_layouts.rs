@@ -0,0 +1,220 @@
+//! Synthetic breach screens of each UI variant. Panels are drawn with the reference colors
+//! and codes are drawn with embedded templates, so the whole pipeline runs without screenshots.
+//! `test/` screenshots show the standard layout only. Real screenshots of tutorial, access point,
+//! single daemon and 2.0 layouts are missing, so these layouts are covered by the synthetic screens only
+
+use bmp::Pixel;
+
+use crate::img::RgbImage;
+use crate::ocr::MatrixTemplates;
+use crate::recognize::{BUFFER_COLOR, CONDITION_BORDER_COLOR, CONDITION_COLOR, MATRIX_COLOR};
+use crate::text::TextGlyphs;
+
+pub const LAYOUT_WIDTH: u32 = 1280;
+pub const LAYOUT_HEIGHT: u32 = 720;
+// matrix and daemon items pitch
+const MATRIX_PITCH: u32 = 50;
+const MATRIX_ROW_PITCH: u32 = 40;
+const DAEMON_ROW_PITCH: u32 = 40;
+const BUFFER_PITCH: u32 = 35;
+// first matrix code offset from matrix border and caption
+const MATRIX_PADDING: u32 = 20;
+// the smallest template size, so a point inside it is inside any code
+const CODE_SIZE: (u32, u32) = (23, 19);
+// description image position inside daemon panel
+const DESCRIPTION_OFFSET: u32 = 350;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// Matrix on the left, daemons to the right of it, buffer above daemons
+    Standard,
+    /// Daemons below matrix, buffer to the right
+    Tutorial,
+    /// Matrix in the right half, daemons and buffer on the left
+    AccessPoint,
+    /// Quickhack upload with a single daemon
+    SingleDaemon,
+    /// 2.0 layout with buffer below daemons
+    Layout2,
+}
+
+pub const VARIANTS: [Variant; 5] = [Variant::Standard, Variant::Tutorial, Variant::AccessPoint, Variant::SingleDaemon, Variant::Layout2];
+
+/// Drawn screen with what must be recognized
+pub struct Fixture {
+    pub img: RgbImage,
+    /// Inner area like `find_matrix_area` returns
+    pub matrix_area: (u32, u32, u32, u32),
+    pub matrix: Vec<Vec<u8>>,
+    pub conditions: Vec<Vec<u8>>,
+    /// Columns each daemon is shifted right by
+    pub offsets: Vec<usize>,
+    pub buffer_size: usize,
+}
+
+impl Fixture {
+    /// Matrix cell `(x, y)`, whose code is drawn under screen point
+    pub fn cell_at(&self, (px, py): (u32, u32)) -> Option<(usize, usize)> {
+        let (left, top) = (self.matrix_area.0 - 1 + MATRIX_PADDING, self.matrix_area.1 + MATRIX_PADDING);
+        if px < left || py < top || (px - left) % MATRIX_PITCH >= CODE_SIZE.0 || (py - top) % MATRIX_ROW_PITCH >= CODE_SIZE.1 {
+            return None;
+        }
+        let (x, y) = (((px - left) / MATRIX_PITCH) as usize, ((py - top) / MATRIX_ROW_PITCH) as usize);
+        return if y < self.matrix.len() && x < self.matrix[y].len() { Some((x, y)) } else { None };
+    }
+}
+
+fn matrix_codes() -> Vec<Vec<u8>> {
+    vec![
+        vec![0x1C, 0x55, 0x7A, 0xBD, 0xE9],
+        vec![0xE9, 0xFF, 0x1C, 0x55, 0x7A],
+        vec![0x7A, 0xBD, 0xE9, 0xFF, 0x1C],
+        vec![0x55, 0x1C, 0xFF, 0xE9, 0xBD],
+        vec![0xBD, 0x7A, 0x55, 0x1C, 0xFF],
+    ]
+}
+
+pub fn render(variant: Variant) -> Fixture {
+    let conditions = match variant {
+        Variant::SingleDaemon => vec![vec![0x1C, 0x55, 0x7A]],
+        _ => vec![vec![0x1C, 0x55], vec![0xBD, 0xE9, 0xFF], vec![0x7A, 0x1C, 0x1C, 0xFF]],
+    };
+    // (matrix left, matrix top), (daemon panel left, top) and (buffer left, top)
+    let (matrix, daemons, buffer) = match variant {
+        Variant::Standard | Variant::SingleDaemon => ((60, 300), (440, 300), (460, 170)),
+        Variant::Tutorial => ((60, 40), (60, 400), (700, 100)),
+        Variant::AccessPoint => ((800, 300), (60, 300), (80, 120)),
+        Variant::Layout2 => ((60, 300), (440, 300), (460, 540)),
+    };
+    let offsets = vec![0; conditions.len()];
+    let buffer_size = 4 + conditions.len() % 2;
+    let mut screen = Screen::new();
+    let matrix_area = screen.draw_matrix(matrix, &matrix_codes());
+    screen.draw_daemons(daemons, &conditions, &offsets, &[]);
+    screen.draw_buffer(buffer, buffer_size);
+    return Fixture { img: screen.img, matrix_area, matrix: matrix_codes(), conditions, offsets, buffer_size };
+}
+
+/// Standard layout with a daemon per title. Titles are drawn with the bundled font instead of description blocks
+pub fn render_titles(titles: &[&str]) -> Fixture {
+    let conditions: Vec<Vec<u8>> = (0..titles.len()).map(|i| matrix_codes()[i][..2].to_vec()).collect();
+    let offsets = vec![0; conditions.len()];
+    let mut screen = Screen::new();
+    let matrix_area = screen.draw_matrix((60, 300), &matrix_codes());
+    screen.draw_daemons((440, 300), &conditions, &offsets, titles);
+    screen.draw_buffer((460, 170), 4);
+    return Fixture { img: screen.img, matrix_area, matrix: matrix_codes(), conditions, offsets, buffer_size: 4 };
+}
+
+/// Standard layout with a 3x3 matrix, so a daemon panel with 4+ daemons ends below the matrix
+pub fn render_daemons(conditions: Vec<Vec<u8>>, offsets: Vec<usize>) -> Fixture {
+    debug_assert_eq!(conditions.len(), offsets.len());
+    let matrix: Vec<Vec<u8>> = matrix_codes().into_iter().take(3).map(|row| row[..3].to_vec()).collect();
+    let mut screen = Screen::new();
+    let matrix_area = screen.draw_matrix((60, 300), &matrix);
+    screen.draw_daemons((440, 310), &conditions, &offsets, &[]);
+    screen.draw_buffer((460, 170), 6);
+    return Fixture { img: screen.img, matrix_area, matrix, conditions, offsets, buffer_size: 6 };
+}
+
+struct Screen {
+    img: RgbImage,
+    templates: MatrixTemplates,
+}
+
+impl Screen {
+    fn new() -> Self {
+        Self { img: RgbImage::new(LAYOUT_WIDTH, LAYOUT_HEIGHT), templates: MatrixTemplates::load_templates() }
+    }
+
+    fn fill(&mut self, (left, top, right, bottom): (u32, u32, u32, u32), color: Pixel) {
+        for y in top..bottom {
+            for x in left..right {
+                self.img.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    fn draw_code(&mut self, code: u8, left: u32, top: u32, color: Pixel) {
+        let template = self.templates.template(code).unwrap();
+        let mut pixels = Vec::new();
+        for y in 0..template.height() {
+            for x in 0..template.width() {
+                if template.pixel(x, y) > 127 {
+                    pixels.push((left + x, top + y));
+                }
+            }
+        }
+        for (x, y) in pixels {
+            self.img.set_pixel(x, y, color);
+        }
+    }
+
+    // █████████████████  <- caption
+    // │ codes         │
+    // └───────────────┘
+    fn draw_matrix(&mut self, (left, top): (u32, u32), codes: &[Vec<u8>]) -> (u32, u32, u32, u32) {
+        let (right, caption_bottom) = (left + 360, top + 20);
+        let bottom = caption_bottom + 40 + codes.len() as u32 * MATRIX_ROW_PITCH;
+        self.fill((left, top, right, caption_bottom), MATRIX_COLOR);
+        self.fill((left, caption_bottom, left + 1, bottom), MATRIX_COLOR);
+        self.fill((right - 1, caption_bottom, right, bottom), MATRIX_COLOR);
+        self.fill((left, bottom - 1, right, bottom), MATRIX_COLOR);
+        for (y, row) in codes.iter().enumerate() {
+            for (x, &code) in row.iter().enumerate() {
+                self.draw_code(code, left + MATRIX_PADDING + x as u32 * MATRIX_PITCH, caption_bottom + MATRIX_PADDING + y as u32 * MATRIX_ROW_PITCH, MATRIX_COLOR);
+            }
+        }
+        return (left + 1, caption_bottom, right - 2, bottom - 2);
+    }
+
+    // ─────────────────────────────  <- top line
+    //  codes             ▬ description or title
+    // ─────────────────────────────  <- bottom line
+    fn draw_daemons(&mut self, (left, top): (u32, u32), conditions: &[Vec<u8>], offsets: &[usize], titles: &[&str]) {
+        let right = left + 400;
+        let bottom = top + 30 + conditions.len() as u32 * DAEMON_ROW_PITCH;
+        self.fill((left, top, right, top + 1), CONDITION_BORDER_COLOR);
+        self.fill((left, bottom - 1, right, bottom), CONDITION_BORDER_COLOR);
+        for (y, (row, &offset)) in conditions.iter().zip(offsets).enumerate() {
+            let row_top = top + 25 + y as u32 * DAEMON_ROW_PITCH;
+            for (x, &code) in row.iter().enumerate() {
+                self.draw_code(code, left + 10 + (offset + x) as u32 * MATRIX_PITCH, row_top, CONDITION_COLOR);
+            }
+            match titles.get(y) {
+                Some(title) => self.draw_text(title, left + DESCRIPTION_OFFSET, row_top),
+                None => self.fill((left + DESCRIPTION_OFFSET, row_top, left + DESCRIPTION_OFFSET + 20, row_top + 10), CONDITION_BORDER_COLOR),
+            }
+        }
+    }
+
+    // glyphs are 1 px apart
+    fn draw_text(&mut self, text: &str, left: u32, top: u32) {
+        let font = TextGlyphs::load_font();
+        let mut x = left;
+        for c in text.chars() {
+            let glyph = font.glyph(c).unwrap();
+            for gy in 0..glyph.height() {
+                for gx in 0..glyph.width() {
+                    if glyph.pixel(gx, gy) != 0 {
+                        self.img.set_pixel(x + gx, top + gy, CONDITION_BORDER_COLOR);
+                    }
+                }
+            }
+            x += glyph.width() + 1;
+        }
+    }
+
+    // ──────────────────────┐
+    //  │    │ │    │ │    │ │
+    // ──────────────────────┘
+    fn draw_buffer(&mut self, (left, top): (u32, u32), size: usize) {
+        let right = left + 10 + size as u32 * BUFFER_PITCH;
+        self.fill((right, top, right + 1, top + 45), BUFFER_COLOR);
+        for i in 0..size as u32 {
+            let slot_left = left + 10 + i * BUFFER_PITCH;
+            self.fill((slot_left, top + 8, slot_left + 1, top + 38), BUFFER_COLOR);
+            self.fill((slot_left + 24, top + 8, slot_left + 25, top + 38), BUFFER_COLOR);
+        }
+    }
+}
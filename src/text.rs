@@ -0,0 +1,468 @@
+use std::path::Path;
+
+use crate::img::{GrayImage, into_image, load_mask_from_file};
+use crate::ocr::Daemon;
+use crate::util::strip_comment;
+
+// glyph gap wider than this share of line height is a space
+const SPACE_GAP: f64 = 0.5;
+// penalty per unit of log aspect ratio difference between glyph and template
+const ASPECT_WEIGHT: f64 = 0.25;
+// glyph is rejected if it matches no template better
+const MAX_GLYPH_ERROR: f64 = 0.35;
+// printed instead of rejected glyph
+const UNKNOWN_CHAR: char = '?';
+
+// hand-drawn 5x7 uppercase font of daemon titles, a fallback for a font pack of `train::train_font`.
+// Templates are trimmed to the glyph width
+const FONT: [(char, [&str; 7]); 40] = [
+    ('A', [".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('B', ["####.", "#...#", "#...#", "####.", "#...#", "#...#", "####."]),
+    ('C', [".###.", "#...#", "#....", "#....", "#....", "#...#", ".###."]),
+    ('D', ["####.", "#...#", "#...#", "#...#", "#...#", "#...#", "####."]),
+    ('E', ["#####", "#....", "#....", "####.", "#....", "#....", "#####"]),
+    ('F', ["#####", "#....", "#....", "####.", "#....", "#....", "#...."]),
+    ('G', [".###.", "#...#", "#....", "#.###", "#...#", "#...#", ".####"]),
+    ('H', ["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..###", "...#.", "...#.", "...#.", "...#.", "#..#.", ".##.."]),
+    ('K', ["#...#", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "#...#"]),
+    ('L', ["#....", "#....", "#....", "#....", "#....", "#....", "#####"]),
+    ('M', ["#...#", "##.##", "#.#.#", "#.#.#", "#...#", "#...#", "#...#"]),
+    ('N', ["#...#", "#...#", "##..#", "#.#.#", "#..##", "#...#", "#...#"]),
+    ('O', [".###.", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('P', ["####.", "#...#", "#...#", "####.", "#....", "#....", "#...."]),
+    ('Q', [".###.", "#...#", "#...#", "#...#", "#.#.#", "#..#.", ".##.#"]),
+    ('R', ["####.", "#...#", "#...#", "####.", "#.#..", "#..#.", "#...#"]),
+    ('S', [".####", "#....", "#....", ".###.", "....#", "....#", "####."]),
+    ('T', ["#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#.."]),
+    ('U', ["#...#", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('V', ["#...#", "#...#", "#...#", "#...#", "#...#", ".#.#.", "..#.."]),
+    ('W', ["#...#", "#...#", "#...#", "#.#.#", "#.#.#", "#.#.#", ".#.#."]),
+    ('X', ["#...#", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "#...#"]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#..", "..#.."]),
+    ('Z', ["#####", "....#", "...#.", "..#..", ".#...", "#....", "#####"]),
+    ('0', [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###."]),
+    ('1', [".#.", "##.", ".#.", ".#.", ".#.", ".#.", "###"]),
+    ('2', [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####"]),
+    ('3', ["#####", "...#.", "..#..", "...#.", "....#", "#...#", ".###."]),
+    ('4', ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#."]),
+    ('5', ["#####", "#....", "####.", "....#", "....#", "#...#", ".###."]),
+    ('6', ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###."]),
+    ('7', ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#..."]),
+    ('8', [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###."]),
+    ('9', [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##.."]),
+    ('_', [".....", ".....", ".....", ".....", ".....", ".....", "#####"]),
+    ('-', [".....", ".....", ".....", "#####", ".....", ".....", "....."]),
+    ('.', [".", ".", ".", ".", ".", ".", "#"]),
+    (':', [".", ".", "#", ".", ".", "#", "."]),
+];
+
+// characters of breach timer, e.g. `14.56` or `0:17`
+const TIMER_CHARS: &str = "0123456789.:";
+
+/// Manifest file name inside font pack directory
+pub const FONT_MANIFEST: &str = "font.txt";
+
+/// Character templates, white glyph on black background spanning the whole line height
+pub struct TextGlyphs {
+    glyphs: Vec<(char, GrayImage)>,
+}
+
+impl TextGlyphs {
+    /// Bundled 5x7 font
+    pub fn load_font() -> Self {
+        let glyphs = FONT.iter()
+            .map(|(c, rows)| {
+                let data = rows.iter()
+                    .flat_map(|row| row.bytes().map(|b| if b == b'#' { 255u8 } else { 0u8 }))
+                    .collect();
+                (*c, GrayImage::new(rows[0].len() as u32, rows.len() as u32, data))
+            })
+            .collect();
+        Self { glyphs }
+    }
+
+    /// Bundled font limited to timer characters, so `0` is never read as `O`
+    pub fn load_timer_font() -> Self {
+        return Self::load_font().timer_glyphs();
+    }
+
+    /// Glyphs of timer characters only
+    pub fn timer_glyphs(mut self) -> Self {
+        self.glyphs.retain(|(c, _)| TIMER_CHARS.contains(*c));
+        return self;
+    }
+
+    pub fn new(glyphs: Vec<(char, GrayImage)>) -> Self {
+        Self { glyphs }
+    }
+
+    /// Glyph of `c` if the font has it
+    #[cfg(test)]
+    pub fn glyph(&self, c: char) -> Option<&GrayImage> {
+        self.glyphs.iter().find(|(g, _)| *g == c).map(|(_, glyph)| glyph)
+    }
+
+    /// Loads pack from directory with `font.txt` manifest, which `save_pack` writes.
+    /// Each manifest line is `<hex character code> <image path>`, `#` at line start or after whitespace starts a comment.
+    /// Image paths are relative to manifest. Pixels with any channel brighter than 50% are glyph
+    pub fn load_pack<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let manifest_path = dir.as_ref().join(FONT_MANIFEST);
+        let manifest = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| format!("Font manifest {} was not read: {}", manifest_path.display(), e))?;
+
+        let mut glyphs = Vec::new();
+        for (i, line) in manifest.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line = || format!("Bad font manifest line {}: {}", i + 1, line);
+            let (code, file) = line.split_once(char::is_whitespace).ok_or_else(bad_line)?;
+            let c = u32::from_str_radix(code, 16).ok().and_then(char::from_u32).ok_or_else(bad_line)?;
+            glyphs.push((c, load_mask_from_file(dir.as_ref().join(file.trim()))?));
+        }
+
+        if glyphs.is_empty() {
+            return Err(format!("Font manifest {} is empty", manifest_path.display()));
+        }
+        return Ok(Self { glyphs });
+    }
+
+    /// Saves glyphs as bmp images and `font.txt` manifest into `dir`
+    pub fn save_pack<P: AsRef<Path>>(&self, dir: P) -> Result<(), String> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| format!("Directory {} was not created: {}", dir.display(), e))?;
+
+        let mut manifest = "# character file\n".to_owned();
+        for (c, glyph) in self.glyphs.iter() {
+            let file = format!("{:04X}.bmp", *c as u32);
+            into_image(glyph).save(dir.join(&file)).map_err(|e| format!("Glyph {} was not saved: {}", file, e))?;
+            manifest += &format!("{:04X} {}\n", *c as u32, file);
+        }
+        let manifest_path = dir.join(FONT_MANIFEST);
+        return std::fs::write(&manifest_path, manifest)
+            .map_err(|e| format!("Font manifest {} was not saved: {}", manifest_path.display(), e));
+    }
+
+    /// Loads pack at `path` if it is set, otherwise bundled font
+    pub fn load_or_bundled(path: Option<&Path>) -> Result<Self, String> {
+        return match path {
+            Some(path) => Self::load_pack(path),
+            None => Ok(Self::load_font()),
+        };
+    }
+
+    /// Like `load_or_bundled`, but limited to timer characters. Pack must have all digits
+    pub fn load_timer_or_bundled(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::load_timer_font()),
+        };
+        let font = Self::load_pack(path)?.timer_glyphs();
+        if let Some(digit) = ('0'..='9').find(|d| font.glyphs.iter().all(|(c, _)| c != d)) {
+            return Err(format!("Timer font {} has no digit {}", path.display(), digit));
+        }
+        return Ok(font);
+    }
+}
+
+/// Reads the first text line of `img` mask. Unknown glyphs are replaced with `?`
+pub fn ocr_text(img: &GrayImage, glyphs: &TextGlyphs) -> String {
+    match text_lines(img).first() {
+        Some(&(top, bottom)) => ocr_line(img, glyphs, top, bottom),
+        None => String::new(),
+    }
+}
+
+/// Title of each daemon is the text line of `img` description mask, which overlaps daemon row the most.
+/// `img` has the same top as condition mask, daemon rows are taken from it
+pub fn ocr_daemon_names(img: &GrayImage, daemons: &[Daemon], glyphs: &TextGlyphs) -> Vec<String> {
+    title_lines(img, daemons).into_iter()
+        .map(|line| match line {
+            Some((top, bottom)) => ocr_line(img, glyphs, top, bottom),
+            None => String::new(),
+        })
+        .collect()
+}
+
+/// `(top, bottom)` of each daemon title line, see `ocr_daemon_names`
+pub fn title_lines(img: &GrayImage, daemons: &[Daemon]) -> Vec<Option<(u32, u32)>> {
+    let lines = text_lines(img);
+    daemons.iter()
+        .map(|d| {
+            let overlap = |&&(top, bottom): &&(u32, u32)| u32::min(bottom, d.bottom).saturating_sub(u32::max(top, d.top));
+            lines.iter().filter(|l| overlap(l) != 0).max_by_key(overlap).copied()
+        })
+        .collect()
+}
+
+/// Remaining breach time in seconds. `img` is timer mask, glyphs are `TextGlyphs::load_timer_or_bundled`
+pub fn ocr_timer(img: &GrayImage, glyphs: &TextGlyphs) -> Result<f64, String> {
+    let text = ocr_text(img, glyphs);
+    return parse_timer(&text).ok_or_else(|| format!("Bad timer {:?}", text));
+}
+
+/// `SS.CC`, `M:SS` or `M:SS.CC`
+pub fn parse_timer(text: &str) -> Option<f64> {
+    let (minutes, seconds) = match text.split_once(':') {
+        Some((m, s)) => (m.parse::<u32>().ok()?, s),
+        None => (0, text),
+    };
+    if seconds.is_empty() || !seconds.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let seconds = seconds.parse::<f64>().ok()?;
+    return Some(minutes as f64 * 60.0 + seconds);
+}
+
+/// `(top, bottom)` ranges of non-empty rows
+fn text_lines(img: &GrayImage) -> Vec<(u32, u32)> {
+    let mut lines: Vec<(u32, u32)> = Vec::new();
+    for (y, _) in img.rows_usage().into_iter().enumerate().filter(|&(_, used)| used) {
+        let y = y as u32;
+        match lines.last_mut() {
+            Some(line) if line.1 == y => line.1 = y + 1,
+            _ => lines.push((y, y + 1)),
+        }
+    }
+    return lines;
+}
+
+/// `(left, right)` ranges of glyphs in `top..bottom` line, which are split at empty columns
+pub fn glyph_spans(img: &GrayImage, top: u32, bottom: u32) -> Vec<(u32, u32)> {
+    let line = img.crop(0, top, img.width() - 1, bottom - 1);
+    let mut spans: Vec<(u32, u32)> = Vec::new();
+    for (x, _) in line.columns_usage().into_iter().enumerate().filter(|&(_, used)| used) {
+        let x = x as u32;
+        match spans.last_mut() {
+            Some(span) if span.1 == x => span.1 = x + 1,
+            _ => spans.push((x, x + 1)),
+        }
+    }
+    return spans;
+}
+
+/// Matches each glyph of `glyph_spans`
+fn ocr_line(img: &GrayImage, glyphs: &TextGlyphs, top: u32, bottom: u32) -> String {
+    let height = bottom - top;
+    let spans = glyph_spans(img, top, bottom);
+
+    let mut result = String::new();
+    for (i, &(left, right)) in spans.iter().enumerate() {
+        if i > 0 && (left - spans[i - 1].1) as f64 > height as f64 * SPACE_GAP {
+            result.push(' ');
+        }
+        let best = glyphs.glyphs.iter()
+            .map(|(c, template)| (*c, glyph_error(img, left, top, right, bottom, template)))
+            .min_by(|(_, e1), (_, e2)| e1.total_cmp(e2));
+        result.push(match best {
+            Some((c, error)) if error <= MAX_GLYPH_ERROR => c,
+            _ => UNKNOWN_CHAR,
+        });
+    }
+    return result;
+}
+
+/// Share of mismatched template pixels, `img` area `left..right` x `top..bottom` is resized to the template.
+/// Different aspect ratio is penalized, so narrow glyphs do not match wide templates
+fn glyph_error(img: &GrayImage, left: u32, top: u32, right: u32, bottom: u32, template: &GrayImage) -> f64 {
+    let (w, h) = ((right - left) as f64, (bottom - top) as f64);
+    let (tw, th) = (template.width(), template.height());
+    let mismatch = (0..th)
+        .flat_map(|ty| (0..tw).map(move |tx| (tx, ty)))
+        .filter(|&(tx, ty)| {
+            let x = left + ((tx as f64 + 0.5) * w / tw as f64) as u32;
+            let y = top + ((ty as f64 + 0.5) * h / th as f64) as u32;
+            (img.pixel(x, y) != 0) != (template.pixel(tx, ty) != 0)
+        })
+        .count();
+    let aspect = ((w / h) / (tw as f64 / th as f64)).ln().abs();
+    return mismatch as f64 / (tw * th) as f64 + aspect * ASPECT_WEIGHT;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::img::GrayImage;
+    use crate::ocr::{Daemon, DaemonStatus};
+    use crate::text::{FONT_MANIFEST, ocr_daemon_names, ocr_text, ocr_timer, parse_timer, TextGlyphs, FONT};
+
+    // draws `text` with the bundled font scaled by `scale`, 1 column between glyphs and 4 columns per space
+    fn draw(data: &mut [u8], w: u32, left: u32, top: u32, scale: u32, text: &str) {
+        draw_with(&FONT, data, w, left, top, scale, text);
+    }
+
+    fn draw_with(font: &[(char, [&str; 7])], data: &mut [u8], w: u32, left: u32, top: u32, scale: u32, text: &str) {
+        let mut x = left;
+        for c in text.chars() {
+            if c == ' ' {
+                x += 4 * scale;
+                continue;
+            }
+            let rows = font.iter().find(|(f, _)| *f == c).unwrap().1;
+            for (ty, row) in rows.iter().enumerate() {
+                for (tx, b) in row.bytes().enumerate() {
+                    if b != b'#' {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let (px, py) = (x + tx as u32 * scale + dx, top + ty as u32 * scale + dy);
+                            data[(py * w + px) as usize] = 255;
+                        }
+                    }
+                }
+            }
+            x += (rows[0].len() as u32 + 1) * scale;
+        }
+    }
+
+    #[test]
+    fn test_ocr_text() {
+        let glyphs = TextGlyphs::load_font();
+        for (scale, text) in [(1, "DATAMINE_V1"), (3, "ICEPICK"), (2, "CAMERA SHUTDOWN"), (3, "0123456789")] {
+            let (w, h) = (400u32, 30u32);
+            let mut data = vec![0u8; (w * h) as usize];
+            draw(&mut data, w, 3, 2, scale, text);
+            assert_eq!(text, ocr_text(&GrayImage::new(w, h, data), &glyphs), "scale {}", scale);
+        }
+        assert_eq!("", ocr_text(&GrayImage::new(4, 4, vec![0; 16]), &glyphs));
+    }
+
+    #[test]
+    fn test_ocr_text_unknown_glyph() {
+        let glyphs = TextGlyphs::load_font();
+        let (w, h) = (40u32, 10u32);
+        let mut data = vec![0u8; (w * h) as usize];
+        draw(&mut data, w, 1, 1, 1, "AB");
+        // filled box matches no glyph
+        for y in 1..8 {
+            for x in 20..27 {
+                data[(y * w + x) as usize] = 255;
+            }
+        }
+        assert_eq!("AB ?", ocr_text(&GrayImage::new(w, h, data), &glyphs));
+    }
+
+    #[test]
+    fn test_save_pack() {
+        let dir = std::env::temp_dir().join("cyberbot2077_test_save_font");
+        let font = TextGlyphs::new(vec![
+            ('A', GrayImage::new(2, 2, vec![255, 0, 0, 255])),
+            ('_', GrayImage::new(3, 1, vec![255, 255, 255])),
+        ]);
+        font.save_pack(&dir).unwrap();
+
+        let pack = TextGlyphs::load_pack(&dir).unwrap();
+        assert_eq!(2, pack.glyphs.len());
+        for ((c, expected), (actual_c, actual)) in font.glyphs.iter().zip(pack.glyphs.iter()) {
+            assert_eq!(c, actual_c);
+            assert_eq!((expected.width(), expected.height()), (actual.width(), actual.height()));
+            for y in 0..expected.height() {
+                for x in 0..expected.width() {
+                    assert_eq!(expected.pixel(x, y), actual.pixel(x, y));
+                }
+            }
+        }
+
+        // `#` inside a file name is not a comment
+        std::fs::copy(dir.join("0041.bmp"), dir.join("glyph#41.bmp")).unwrap();
+        std::fs::write(dir.join(FONT_MANIFEST), "# font\n41 glyph#41.bmp # letter A\n").unwrap();
+        let pack = TextGlyphs::load_pack(&dir).unwrap();
+        assert_eq!(vec!['A'], pack.glyphs.iter().map(|(c, _)| *c).collect::<Vec<_>>());
+
+        for content in ["", "# comment only\n", "41\n", "XYZ 0041.bmp\n", "41 missing.bmp\n"] {
+            std::fs::write(dir.join(FONT_MANIFEST), content).unwrap();
+            assert!(TextGlyphs::load_pack(&dir).is_err(), "{:?}", content);
+        }
+        // explicit pack never falls back to bundled one
+        assert!(TextGlyphs::load_or_bundled(Some(&dir)).is_err());
+        assert!(TextGlyphs::load_or_bundled(None).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_timer() {
+        assert_eq!(Some(14.56), parse_timer("14.56"));
+        assert_eq!(Some(17.0), parse_timer("0:17"));
+        assert_eq!(Some(65.5), parse_timer("1:05.5"));
+        assert_eq!(None, parse_timer(""));
+        assert_eq!(None, parse_timer("1?.56"));
+        assert_eq!(None, parse_timer("1:"));
+    }
+
+    #[test]
+    fn test_ocr_timer() {
+        let glyphs = TextGlyphs::load_timer_font();
+        for (text, expected) in [("14.56", 14.56), ("0:17", 17.0), ("08.00", 8.0)] {
+            let (w, h) = (100u32, 30u32);
+            let mut data = vec![0u8; (w * h) as usize];
+            draw(&mut data, w, 2, 2, 3, text);
+            assert_eq!(Ok(expected), ocr_timer(&GrayImage::new(w, h, data), &glyphs), "{}", text);
+        }
+        assert!(ocr_timer(&GrayImage::new(4, 4, vec![0; 16]), &glyphs).is_err());
+    }
+
+    // segment digits with square corners and narrow `1` unlike the bundled font
+    const SEGMENT_FONT: [(char, [&str; 7]); 12] = [
+        ('0', ["####", "#..#", "#..#", "#..#", "#..#", "#..#", "####"]),
+        ('1', ["#", "#", "#", "#", "#", "#", "#"]),
+        ('2', ["####", "...#", "...#", "####", "#...", "#...", "####"]),
+        ('3', ["####", "...#", "...#", "####", "...#", "...#", "####"]),
+        ('4', ["#..#", "#..#", "#..#", "####", "...#", "...#", "...#"]),
+        ('5', ["####", "#...", "#...", "####", "...#", "...#", "####"]),
+        ('6', ["####", "#...", "#...", "####", "#..#", "#..#", "####"]),
+        ('7', ["####", "...#", "...#", "...#", "...#", "...#", "...#"]),
+        ('8', ["####", "#..#", "#..#", "####", "#..#", "#..#", "####"]),
+        ('9', ["####", "#..#", "#..#", "####", "...#", "...#", "####"]),
+        ('.', [".", ".", ".", ".", ".", ".", "#"]),
+        (':', [".", ".", "#", ".", ".", "#", "."]),
+    ];
+
+    #[test]
+    fn test_load_timer_pack() {
+        let dir = std::env::temp_dir().join("cyberbot2077_test_timer_font");
+        let glyph = |rows: &[&str; 7]| {
+            let data = rows.iter().flat_map(|row| row.bytes().map(|b| if b == b'#' { 255u8 } else { 0u8 })).collect();
+            GrayImage::new(rows[0].len() as u32, 7, data)
+        };
+        let mut glyphs: Vec<(char, GrayImage)> = SEGMENT_FONT.iter().map(|(c, rows)| (*c, glyph(rows))).collect();
+        // title characters of the same pack are dropped
+        glyphs.push(('O', glyph(&SEGMENT_FONT[0].1)));
+        TextGlyphs::new(glyphs).save_pack(&dir).unwrap();
+
+        let font = TextGlyphs::load_timer_or_bundled(Some(&dir)).unwrap();
+        assert_eq!(12, font.glyphs.len());
+        for (text, expected) in [("14.56", 14.56), ("0:17", 17.0), ("08.00", 8.0), ("23.91", 23.91)] {
+            let (w, h) = (100u32, 30u32);
+            let mut data = vec![0u8; (w * h) as usize];
+            draw_with(&SEGMENT_FONT, &mut data, w, 2, 2, 3, text);
+            assert_eq!(Ok(expected), ocr_timer(&GrayImage::new(w, h, data), &font), "{}", text);
+        }
+
+        TextGlyphs::new(vec![('1', glyph(&SEGMENT_FONT[1].1))]).save_pack(&dir).unwrap();
+        let err = TextGlyphs::load_timer_or_bundled(Some(&dir)).err().unwrap();
+        assert!(err.contains("digit 0"), "{}", err);
+        assert_eq!(12, TextGlyphs::load_timer_or_bundled(None).unwrap().glyphs.len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ocr_daemon_names() {
+        // each title is followed by a smaller description line
+        let glyphs = TextGlyphs::load_font();
+        let (w, h) = (300u32, 100u32);
+        let mut data = vec![0u8; (w * h) as usize];
+        draw(&mut data, w, 0, 5, 2, "DATAMINE_V1");
+        draw(&mut data, w, 0, 22, 1, "EXTRACT EDDIES");
+        draw(&mut data, w, 0, 55, 2, "ICEPICK");
+        draw(&mut data, w, 0, 72, 1, "LOWER RAM COST");
+
+        let daemon = |top, bottom| Daemon { status: DaemonStatus::Pending, codes: Vec::new(), offset: 0, top, bottom, uncertain: Vec::new() };
+        let daemons = [daemon(3, 20), daemon(53, 70), daemon(90, 99)];
+        let names = ocr_daemon_names(&GrayImage::new(w, h, data), &daemons, &glyphs);
+        assert_eq!(vec!["DATAMINE_V1", "ICEPICK", ""], names);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::img::{GrayImage, load_img_from_file, RgbImage};
+use crate::ocr::{MatrixTemplates, ocr_daemons, OcrOptions, segment_glyphs};
+use crate::recognize;
+use crate::recognize::StageFilters;
+use crate::text::{glyph_spans, TextGlyphs, title_lines};
+use crate::util::strip_comment;
+
+// averaged glyph pixel is set if it is brighter, i.e. it is set in most samples
+const FONT_THRESHOLD: u8 = 128;
+
+/// Labels of `screenshot` are stored alongside in `<stem>.matrix.txt`
+pub fn labels_path(screenshot: &Path) -> PathBuf {
+    let stem = screenshot.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    screenshot.with_file_name(format!("{}.matrix.txt", stem))
+}
+
+/// Daemon titles of `screenshot` are stored alongside in `<stem>.daemons.txt`
+pub fn titles_path(screenshot: &Path) -> PathBuf {
+    let stem = screenshot.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    screenshot.with_file_name(format!("{}.daemons.txt", stem))
+}
+
+/// One daemon title per line from top to bottom, empty lines are skipped
+pub fn parse_titles(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Parses matrix rows of hex codes. Codes may be separated by spaces or commas and prefixed with `0x`,
+/// so `test_cases.rs` rows like `vec![0x1C, 0x55],` are accepted as well. `//` and `#` after whitespace start a comment
+pub fn parse_labels(text: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut result = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line).split("//").next().unwrap();
+        let line = line.replace("vec!", " ").replace(['[', ']', ',', ';'], " ");
+        let row = line.split_whitespace()
+            .map(|token| {
+                let hex = token.trim_start_matches("0x").trim_start_matches("0X");
+                u8::from_str_radix(hex, 16).map_err(|_| format!("Bad code {} at line {}", token, i + 1))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        if !row.is_empty() {
+            result.push(row);
+        }
+    }
+    if result.is_empty() {
+        return Err("Labels are empty".to_owned());
+    }
+    return Ok(result);
+}
+
+/// Averages glyph masks of each labeled code. Cell masks are cut at glyph hull, resized to the mean hull size,
+/// and the average is stored as grayscale, so `MatrixTemplates::load_pack` keeps pixels set in most samples
+pub fn train(samples: &[(RgbImage, Vec<Vec<u8>>)], filters: &StageFilters) -> Result<MatrixTemplates, String> {
+    let mut glyphs: BTreeMap<u8, Vec<GrayImage>> = BTreeMap::new();
+    for (i, (img, labels)) in samples.iter().enumerate() {
+        for (code, glyph) in extract_glyphs(img, labels, filters).map_err(|e| format!("Sample #{}: {}", i + 1, e))? {
+            glyphs.entry(code).or_default().push(glyph);
+        }
+    }
+    if glyphs.is_empty() {
+        return Err("No glyphs were extracted".to_owned());
+    }
+
+    let templates = glyphs.into_iter()
+        .map(|(code, glyphs)| (code, average(&glyphs)))
+        .collect();
+    return Ok(MatrixTemplates::new(templates));
+}
+
+/// Averages glyphs of labeled daemon titles. Glyphs are cut at full line height, so `.` and `-` keep their position.
+/// `templates` read daemon codes to find daemon rows
+pub fn train_font(samples: &[(RgbImage, Vec<String>)], templates: &MatrixTemplates, filters: &StageFilters) -> Result<TextGlyphs, String> {
+    let mut glyphs: BTreeMap<char, Vec<GrayImage>> = BTreeMap::new();
+    for (i, (img, titles)) in samples.iter().enumerate() {
+        for (c, glyph) in extract_title_glyphs(img, titles, templates, filters).map_err(|e| format!("Sample #{}: {}", i + 1, e))? {
+            glyphs.entry(c).or_default().push(glyph);
+        }
+    }
+    if glyphs.is_empty() {
+        return Err("No title glyphs were extracted".to_owned());
+    }
+
+    let glyphs = glyphs.into_iter()
+        .map(|(c, glyphs)| {
+            let avg = average(&glyphs);
+            let data = (0..avg.height())
+                .flat_map(|y| (0..avg.width()).map(move |x| (x, y)))
+                .map(|(x, y)| if avg.pixel(x, y) >= FONT_THRESHOLD { 255 } else { 0 })
+                .collect();
+            (c, GrayImage::new(avg.width(), avg.height(), data))
+        })
+        .collect();
+    return Ok(TextGlyphs::new(glyphs));
+}
+
+/// Loads each screenshot with its labels file, trains templates and saves pack into `output` directory.
+/// If each screenshot has titles file as well, font is trained with the new templates and saved into `output/font`.
+/// Returns whether font was saved
+pub fn train_files(output: &Path, screenshots: &[PathBuf], filters: &StageFilters) -> Result<bool, String> {
+    let mut samples = Vec::with_capacity(screenshots.len());
+    let mut title_samples = Vec::with_capacity(screenshots.len());
+    for path in screenshots {
+        let img = load_img_from_file(path)?;
+        let labels_path = labels_path(path);
+        let labels = std::fs::read_to_string(&labels_path)
+            .map_err(|e| format!("Labels {} were not read: {}", labels_path.display(), e))
+            .and_then(|text| parse_labels(&text))?;
+        if let Ok(text) = std::fs::read_to_string(titles_path(path)) {
+            title_samples.push((img.clone(), parse_titles(&text)));
+        }
+        samples.push((img, labels));
+    }
+
+    let templates = train(&samples, filters)?;
+    templates.save_pack(output)?;
+    if title_samples.len() != samples.len() {
+        return Ok(false);
+    }
+    let font = train_font(&title_samples, &templates, filters)?;
+    font.save_pack(output.join("font"))?;
+    return Ok(true);
+}
+
+/// Cuts matrix cell masks at glyph hull
+fn extract_glyphs(img: &RgbImage, labels: &[Vec<u8>], filters: &StageFilters) -> Result<Vec<(u8, GrayImage)>, String> {
+    let area = recognize::find_matrix_area(img, filters).ok_or_else(|| "Matrix was not found".to_owned())?;
+    let mask = GrayImage::filter_by(img, &filters.matrix, area.0, area.1, area.2, area.3);
+    let segmentation = segment_glyphs(&mask)?;
+    if segmentation.rows.len() != labels.len() || labels.iter().any(|row| row.len() != segmentation.columns.len()) {
+        return Err(format!(
+            "Labels do not match {}x{} matrix",
+            segmentation.columns.len(), segmentation.rows.len(),
+        ));
+    }
+
+    let mut result = Vec::new();
+    for (y, (row, labels_row)) in segmentation.rows.iter().zip(labels).enumerate() {
+        for (x, (column, &code)) in segmentation.columns.iter().zip(labels_row).enumerate() {
+            if segmentation.uncertain.contains(&(x, y)) {
+                continue;
+            }
+            if let Some(hull) = mask.rect_hull(column.start, row.start, column.end - 1, row.end - 1) {
+                result.push((code, mask.crop(hull.0, hull.1, hull.2, hull.3)));
+            }
+        }
+    }
+    return Ok(result);
+}
+
+/// Cuts title glyphs of each daemon row. Title characters except spaces have to match glyphs one by one
+fn extract_title_glyphs(img: &RgbImage, titles: &[String], templates: &MatrixTemplates, filters: &StageFilters) -> Result<Vec<(char, GrayImage)>, String> {
+    let layout = recognize::find_layout(img, filters)?;
+    let area = layout.descriptions.ok_or_else(|| "Daemon descriptions were not found".to_owned())?;
+    let condition_area = layout.conditions;
+    let filter = |filter| GrayImage::filter_by(img, filter, condition_area.0, condition_area.1, condition_area.2, condition_area.3);
+    let daemons = ocr_daemons(&filter(&filters.condition), &filter(&filters.installed), &filter(&filters.failed), templates, &OcrOptions::default())?;
+    if daemons.len() != titles.len() {
+        return Err(format!("{} titles do not match {} daemons", titles.len(), daemons.len()));
+    }
+
+    // description top is the same as condition top
+    let mask = GrayImage::filter_by(img, &filters.condition_border, area.0, area.1, area.2, area.3);
+    let mut result = Vec::new();
+    for (line, title) in title_lines(&mask, &daemons).into_iter().zip(titles) {
+        let (top, bottom) = line.ok_or_else(|| format!("Title {:?} was not found", title))?;
+        let spans = glyph_spans(&mask, top, bottom);
+        let chars: Vec<char> = title.chars().filter(|c| !c.is_whitespace()).collect();
+        if spans.len() != chars.len() {
+            return Err(format!("Title {:?} does not match {} glyphs", title, spans.len()));
+        }
+        for (&c, &(left, right)) in chars.iter().zip(spans.iter()) {
+            result.push((c, mask.crop(left, top, right - 1, bottom - 1)));
+        }
+    }
+    return Ok(result);
+}
+
+/// Mean intensity of glyphs resized to their mean size
+fn average(glyphs: &[GrayImage]) -> GrayImage {
+    let n = glyphs.len() as u32;
+    let w = (glyphs.iter().map(|g| g.width()).sum::<u32>() + n / 2) / n;
+    let h = (glyphs.iter().map(|g| g.height()).sum::<u32>() + n / 2) / n;
+
+    let mut sums = vec![0u32; (w * h) as usize];
+    for glyph in glyphs {
+        for y in 0..h {
+            let src_y = y * glyph.height() / h;
+            for x in 0..w {
+                let src_x = x * glyph.width() / w;
+                sums[(y * w + x) as usize] += glyph.pixel(src_x, src_y) as u32;
+            }
+        }
+    }
+    let data = sums.into_iter().map(|sum| (sum / n) as u8).collect();
+    return GrayImage::new(w, h, data);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::img::{GrayImage, load_img_from_file, RgbImage};
+    use crate::ocr::{codes, MatrixTemplates, OcrOptions};
+    use crate::recognize;
+    use crate::recognize::StageFilters;
+    use crate::test_cases::{FILE1, FILE2, FILE3, FILE4, matrix1, matrix2, matrix3, matrix4, MATRIX_AREA4};
+    use crate::test_layouts::{render, render_titles, Variant};
+    use crate::text::{ocr_daemon_names, TextGlyphs};
+    use crate::train::{average, labels_path, parse_labels, parse_titles, titles_path, train, train_font};
+
+    #[test]
+    fn test_parse_labels() {
+        let expected = vec![vec![0x1C, 0x55], vec![0xBD, 0xFF, 0xE9]];
+        assert_eq!(Ok(expected.clone()), parse_labels("1C 55\n\nBD FF e9 # comment\n"));
+        assert_eq!(Ok(expected), parse_labels("vec![\n    vec![0x1C, 0x55],\n    vec![0xBD, 0xFF, 0xE9], // row\n]\n"));
+        assert!(parse_labels("1C 5X\n").is_err());
+        assert!(parse_labels("1C 55#\n").is_err());
+        assert!(parse_labels("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn test_labels_path() {
+        assert_eq!(Path::new("test/test1.matrix.txt"), labels_path(Path::new("test/test1.bmp")));
+        assert_eq!(Path::new("test/test1.daemons.txt"), titles_path(Path::new("test/test1.bmp")));
+    }
+
+    #[test]
+    fn test_parse_titles() {
+        assert_eq!(vec!["ICEPICK", "DATAMINE_V1"], parse_titles("  ICEPICK\n\nDATAMINE_V1  \n"));
+        assert!(parse_titles("\n").is_empty());
+    }
+
+    #[test]
+    fn test_average() {
+        let a = GrayImage::new(2, 2, vec![
+            255, 255,
+            000, 255,
+        ]);
+        let b = GrayImage::new(2, 2, vec![
+            255, 000,
+            000, 255,
+        ]);
+        let c = GrayImage::new(2, 2, vec![
+            255, 255,
+            255, 255,
+        ]);
+        let avg = average(&[a, b, c]);
+        assert_eq!(2, avg.width());
+        assert_eq!(255, avg.pixel(0, 0));
+        assert_eq!(170, avg.pixel(1, 0));
+        assert_eq!(85, avg.pixel(0, 1));
+        assert_eq!(255, avg.pixel(1, 1));
+
+        // glyphs are resized to the mean size
+        let big = GrayImage::new(4, 4, vec![255; 16]);
+        let avg = average(&[GrayImage::new(2, 2, vec![0; 4]), big]);
+        assert_eq!(3, avg.width());
+        assert_eq!(3, avg.height());
+        assert_eq!(127, avg.pixel(2, 2));
+    }
+
+    #[test]
+    fn test_train() {
+        let filters = StageFilters::default();
+        let samples = vec![
+            (load_img_from_file(FILE1).unwrap(), matrix1()),
+            (load_img_from_file(FILE2).unwrap(), matrix2()),
+            (load_img_from_file(FILE3).unwrap(), matrix3()),
+        ];
+        let templates = train(&samples, &filters).unwrap();
+
+        let img = load_img_from_file(FILE4).unwrap();
+        let state = recognize::read_matrix(&img, &MATRIX_AREA4, &filters, &templates, &OcrOptions::default()).unwrap();
+        assert_eq!(matrix4(), codes(&state.code_cells().unwrap()));
+    }
+
+    /// Daemon names of `img` read with `font`
+    fn read_names(img: &RgbImage, font: &TextGlyphs) -> Vec<String> {
+        let filters = StageFilters::default();
+        let templates = MatrixTemplates::load_templates();
+        let layout = recognize::find_layout(img, &filters).unwrap();
+        let area = layout.descriptions.unwrap();
+        let daemons = recognize::read_daemons(img, &layout.conditions, &filters, &templates, &OcrOptions::default()).unwrap();
+        let mask = GrayImage::filter_by(img, &filters.condition_border, area.0, area.1, area.2, area.3);
+        return ocr_daemon_names(&mask, &daemons, font);
+    }
+
+    #[test]
+    fn test_train_font_layout() {
+        // each synthetic description is a single block glyph
+        let filters = StageFilters::default();
+        let templates = MatrixTemplates::load_templates();
+        let fixture = render(Variant::Standard);
+        let titles = vec!["X".to_owned(); fixture.conditions.len()];
+        let font = train_font(&[(fixture.img.clone(), titles.clone())], &templates, &filters).unwrap();
+        assert_eq!(titles, read_names(&fixture.img, &font));
+
+        assert!(train_font(&[(fixture.img.clone(), vec!["X".to_owned()])], &templates, &filters).is_err());
+        let wide = vec!["XY".to_owned(); fixture.conditions.len()];
+        assert!(train_font(&[(fixture.img.clone(), wide)], &templates, &filters).is_err());
+    }
+
+    #[test]
+    fn test_train_font_titles() {
+        // glyphs of the first screens are cut from whole titles, the last screen has unseen titles of the same characters
+        let filters = StageFilters::default();
+        let templates = MatrixTemplates::load_templates();
+        let samples: Vec<(RgbImage, Vec<String>)> = [["ICEPICK", "MASS_V1", "TURRET"], ["DATAMIN", "CAMERA", "WEAKEN"]].iter()
+            .map(|titles| (render_titles(titles).img, titles.iter().map(|t| t.to_string()).collect()))
+            .collect();
+        let font = train_font(&samples, &templates, &filters).unwrap();
+        assert_eq!(vec!["TRACKER", "SPIKE_V1", "MAINCAM"], read_names(&render_titles(&["TRACKER", "SPIKE_V1", "MAINCAM"]).img, &font));
+    }
+
+    #[test]
+    #[ignore = "test/*.daemons.txt title labels of test screenshots are not in the repository yet"]
+    fn test_train_font() {
+        // titles of each screenshot are in `test/<name>.daemons.txt`
+        let filters = StageFilters::default();
+        let templates = MatrixTemplates::load_templates();
+        let titles = |file: &str| parse_titles(&std::fs::read_to_string(titles_path(Path::new(file))).unwrap());
+        let samples: Vec<(RgbImage, Vec<String>)> = [FILE1, FILE2, FILE3].iter()
+            .map(|&file| (load_img_from_file(file).unwrap(), titles(file)))
+            .collect();
+        let font = train_font(&samples, &templates, &filters).unwrap();
+
+        assert_eq!(titles(FILE4), read_names(&load_img_from_file(FILE4).unwrap(), &font));
+    }
+}
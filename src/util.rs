@@ -5,6 +5,18 @@ pub fn new_vec<T: Copy>(a: &[T], b: &[T]) -> Vec<T> {
     return r;
 }
 
+/// Cuts `#` comment off, which starts a line or follows whitespace, so `#` inside a word is kept
+pub fn strip_comment(line: &str) -> &str {
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && prev.map_or(true, char::is_whitespace) {
+            return &line[..i];
+        }
+        prev = Some(c);
+    }
+    return line;
+}
+
 pub fn is_part_of<T: Eq>(main: &[T], part: &[T]) -> bool {
     if main.is_empty() || part.is_empty() || part.len() > main.len() {
         return false;
@@ -39,7 +51,7 @@ pub fn union_point<T: Eq>(c1: &[T], c2: &[T]) -> Option<usize> {
 
 #[cfg(test)]
 mod tests {
-    use crate::util::{is_part_of, new_vec, union_point};
+    use crate::util::{is_part_of, new_vec, strip_comment, union_point};
 
     #[test]
     fn test_new_vec() {
@@ -60,6 +72,16 @@ mod tests {
         assert_eq!(4, v4.capacity());
     }
 
+    #[test]
+    fn test_strip_comment() {
+        assert_eq!("", strip_comment("# comment"));
+        assert_eq!("1C 1c.bmp ", strip_comment("1C 1c.bmp # comment"));
+        assert_eq!("1C 1c.bmp\t", strip_comment("1C 1c.bmp\t#comment"));
+        assert_eq!("1C glyph#1.bmp", strip_comment("1C glyph#1.bmp"));
+        assert_eq!("1C glyph#1.bmp ", strip_comment("1C glyph#1.bmp # comment"));
+        assert_eq!("", strip_comment(""));
+    }
+
     #[test]
     fn test_is_part_of() {
         assert_eq!(true, is_part_of(&vec![0, 1, 2, 3, 4], &vec![0, 1, 2, 3, 4]));